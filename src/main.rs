@@ -1,72 +1,867 @@
-use anyhow::Result;
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Days, Local, NaiveDate, Utc};
+use clap::{Parser, Subcommand};
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    queue,
+    terminal::{self, ClearType},
+};
+use rand::{rngs::StdRng, seq::IndexedRandom, RngExt, SeedableRng};
 use serde::{Deserialize, Serialize};
 use std::{
-    fs,
-    io::{self, Write},
-    path::PathBuf,
+    cell::Cell,
+    collections::{hash_map::Entry, BTreeMap, HashMap, HashSet},
+    fmt, fs,
+    io::{self, IsTerminal, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+    time::SystemTime,
 };
 
 const DATA_FILE: &str = "library_data.json";
+const DATA_ENV_VAR: &str = "BOOK_LENDING_DATA";
+/// Format version written to the data file. Version 1 files predate the
+/// field and have no loans.
+const DATA_VERSION: u32 = 2;
+const DEFAULTS_OVERRIDE_FILE: &str = "default_library.json";
+const EMBEDDED_DEFAULTS: &str = include_str!("default_library.json");
+const DEMO_SEED: u64 = 0x5EED_B00C;
+const USAGE_FILE: &str = "usage_stats.json";
+const HISTORY_FILE: &str = "history.jsonl";
+const DEFAULT_HISTORY_ENTRIES: usize = 20;
+const DEFAULT_LOAN_DAYS: u64 = 14;
+const BACKUP_DIR: &str = "backups";
+const BACKUP_LIMIT: usize = 10;
 
-#[derive(Serialize, Deserialize, Clone)]
+const MAIN_MENU: &[&str] = &[
+    "View available books",
+    "View borrowed books",
+    "Borrow a book",
+    "Return a book",
+    "Search all fields",
+    "View all books",
+    "Filter by availability status",
+    "Quick filter (live)",
+    "Search and borrow",
+    "Catalog maintenance",
+    "Reports",
+    "Settings",
+    "Exit",
+];
+const MAINTENANCE_MENU: &[&str] = &[
+    "Rename an author",
+    "Merge two authors",
+    "Suspend or unsuspend a book",
+    "Print a spine label",
+    "Check copy counts",
+    "Compact and tidy the data file",
+    "Clear a review flag",
+    "Import missing books from a file",
+    "Search and replace in titles",
+    "Set a replacement cost",
+    "Preview lowering a copy count",
+    "Export a clean copy of the catalog",
+    "Set genre for search results",
+    "Clean up books with zero copies",
+    "Show a barcode payload",
+    "Add a book",
+    "Remove a book",
+    "Export the catalog to CSV",
+    "Import the catalog from CSV",
+    "Fix duplicate ids",
+    "Back",
+];
+const REPORTS_MENU: &[&str] = &[
+    "Catalog summary",
+    "Books down to their last copy",
+    "Recently modified books",
+    "Most borrowed right now",
+    "Usage statistics",
+    "Inventory value and risk",
+    "Group by field",
+    "Titles containing digits",
+    "Borrowing history",
+    "Back",
+];
+const SETTINGS_MENU: &[&str] = &[
+    "Spine label format",
+    "Row numbering",
+    "Return condition prompt",
+    "Compact listings by default",
+    "Save usage statistics",
+    "High-value threshold",
+    "Always show available and borrowed columns",
+    "Shared file mode",
+    "Checkout limit",
+    "Back",
+];
+const DEFAULT_TERMINAL_WIDTH: usize = 80;
+const DEFAULT_TERMINAL_HEIGHT: usize = 24;
+
+/// Interactive command-line tool for a small library. Run without a
+/// subcommand to use the menu.
+#[derive(Parser)]
+#[command(version)]
+struct Options {
+    /// List books in a multi-column grid sized to the terminal width.
+    #[arg(long)]
+    grid: bool,
+    /// Print a one-line catalog summary and exit.
+    #[arg(long)]
+    kpi: bool,
+    #[arg(long, value_name = "COUNT", hide = true)]
+    seed_demo: Option<usize>,
+    /// Data file to use instead of $BOOK_LENDING_DATA or ./library_data.json.
+    #[arg(long, value_name = "PATH")]
+    data: Option<PathBuf>,
+    /// Compare the data file with another library file and exit.
+    #[arg(long, value_name = "PATH")]
+    diff: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Session-only override of the persisted compact listing setting.
+    #[arg(skip)]
+    compact: Option<bool>,
+    /// Session-only listing order; `None` keeps catalog order.
+    #[arg(skip)]
+    sort: Option<SortOrder>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List every book.
+    List {
+        /// Sort by title, author, available, or id.
+        #[arg(long, value_parser = parse_sort_key)]
+        sort: Option<SortKey>,
+        /// Reverse the sort order.
+        #[arg(long, requires = "sort")]
+        descending: bool,
+    },
+    /// Borrow one copy of a book.
+    Borrow {
+        id: String,
+        /// Record who has the copy.
+        #[arg(long)]
+        borrower: Option<String>,
+        /// Loan length in days; only recorded for a named borrower.
+        #[arg(
+            long,
+            requires = "borrower",
+            default_value_t = DEFAULT_LOAN_DAYS,
+            value_parser = clap::value_parser!(u64).range(1..)
+        )]
+        days: u64,
+    },
+    /// Return one copy of a book.
+    Return {
+        id: String,
+        /// Who is returning it, when the book is on loan to several people.
+        #[arg(long)]
+        borrower: Option<String>,
+    },
+    /// Write the catalog to a CSV file ("-" for standard output).
+    ExportCsv { path: PathBuf },
+    /// Replace the catalog with the books in a CSV file.
+    ImportCsv { path: PathBuf },
+    /// Add a book with the next free id.
+    Add {
+        #[arg(long)]
+        title: String,
+        #[arg(long)]
+        author: String,
+        #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+        copies: u32,
+        /// Replacement cost per copy, e.g. 12.50.
+        #[arg(long, value_parser = parse_cost)]
+        cost: Option<f64>,
+    },
+}
+
+fn parse_sort_key(text: &str) -> Result<SortKey, String> {
+    SortKey::from_prefix(text).ok_or_else(|| "expected title, author, available, or id".into())
+}
+
+fn parse_cost(text: &str) -> Result<f64, String> {
+    parse_amount(text).ok_or_else(|| "expected a non-negative amount, e.g. 12.50".into())
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
 struct Book {
     id: String,
     title: String,
     author: String,
     copies_total: u32,
     copies_available: u32,
+    #[serde(default, skip_serializing_if = "is_default")]
+    suspended: bool,
+    #[serde(default, skip_serializing_if = "is_default")]
+    suspend_reason: String,
+    /// Set when a copy comes back damaged, until staff have looked at it.
+    #[serde(default, skip_serializing_if = "is_default")]
+    needs_review: bool,
+    #[serde(default, skip_serializing_if = "is_default")]
+    genre: String,
+    /// Cost to replace a single copy.
+    #[serde(default, skip_serializing_if = "is_default")]
+    replacement_cost: f64,
+    /// When the record was last edited, borrowed, or returned.
+    #[serde(default, skip_serializing_if = "is_default")]
+    updated_at: Option<DateTime<Utc>>,
+    /// Who has each borrowed copy. Copies borrowed before loans were tracked
+    /// have no entry here.
+    #[serde(default, skip_serializing_if = "is_default")]
+    loans: Vec<Loan>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct Loan {
+    borrower: String,
+    due: NaiveDate,
+}
+
+fn touch(book: &mut Book) {
+    book.updated_at = Some(Utc::now());
+}
+
+fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+struct LabelFormat {
+    /// Characters per line inside the label border.
+    width: usize,
+    /// Maximum number of lines the title may wrap onto.
+    title_lines: usize,
+}
+
+impl Default for LabelFormat {
+    fn default() -> Self {
+        LabelFormat {
+            width: 12,
+            title_lines: 2,
+        }
+    }
+}
+
+/// How the `#` column in book listings is numbered.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+enum Numbering {
+    /// 1..n within the current view; changes as views are filtered.
+    #[default]
+    Sequential,
+    /// Position in the catalog, stable across every view.
+    Catalog,
+    /// The view number plus the catalog position in its own column.
+    Both,
+}
+
+impl Numbering {
+    const ALL: [Numbering; 3] = [Numbering::Sequential, Numbering::Catalog, Numbering::Both];
+
+    fn describe(self) -> &'static str {
+        match self {
+            Numbering::Sequential => "Sequential per view",
+            Numbering::Catalog => "Stable catalog position",
+            Numbering::Both => "Both view number and catalog position",
+        }
+    }
+
+    fn headers(self) -> Vec<String> {
+        match self {
+            Numbering::Sequential | Numbering::Catalog => vec!["#".into()],
+            Numbering::Both => vec!["#".into(), "Pos".into()],
+        }
+    }
+
+    fn labels(self, display_idx: usize, book_index: usize) -> Vec<String> {
+        match self {
+            Numbering::Sequential => vec![(display_idx + 1).to_string()],
+            Numbering::Catalog => vec![(book_index + 1).to_string()],
+            Numbering::Both => vec![(display_idx + 1).to_string(), (book_index + 1).to_string()],
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+struct Settings {
+    label: LabelFormat,
+    numbering: Numbering,
+    /// Ask whether a returned copy is good, damaged, or lost.
+    prompt_return_condition: bool,
+    /// One line per book instead of the full table in listings.
+    compact_listings: bool,
+    /// Keep menu usage counts in `usage_stats.json` between sessions.
+    persist_usage_stats: bool,
+    /// Per-copy replacement cost above which a borrowed title is called out.
+    high_value_threshold: f64,
+    /// Show both the Available and Borrowed columns in every listing.
+    show_both_counts: bool,
+    /// Reload before borrow/return if another process changed the data file.
+    shared_mode: bool,
+    /// Maximum copies out at once across the whole library; `None` is unlimited.
+    checkout_limit: Option<u32>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            label: LabelFormat::default(),
+            numbering: Numbering::default(),
+            prompt_return_condition: false,
+            compact_listings: false,
+            persist_usage_stats: false,
+            high_value_threshold: 50.0,
+            show_both_counts: false,
+            shared_mode: false,
+            checkout_limit: None,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 struct Library {
+    #[serde(default)]
+    version: u32,
     books: Vec<Book>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    settings: Settings,
+    /// Lowercased book ID -> position in `books`. Derived data, rebuilt on load.
+    #[serde(skip)]
+    id_index: HashMap<String, usize>,
+    /// Modification time of the data file as of our last load or save.
+    #[serde(skip)]
+    file_mtime: Cell<Option<SystemTime>>,
+    /// Format version the file was loaded from, until the upgrade is saved.
+    #[serde(skip)]
+    upgraded_from: Option<u32>,
+}
+
+impl Library {
+    fn rebuild_index(&mut self) {
+        self.id_index.clear();
+        for (idx, book) in self.books.iter().enumerate() {
+            // Keep the first occurrence so duplicate IDs resolve the same way a scan would.
+            self.id_index.entry(book.id.to_lowercase()).or_insert(idx);
+        }
+    }
+
+    fn find_by_id(&self, id: &str) -> Option<usize> {
+        self.id_index.get(&id.to_lowercase()).copied()
+    }
+
+    fn remember_file_state(&self) {
+        self.file_mtime.set(file_modified(&data_path()));
+    }
+
+    fn changed_on_disk(&self) -> bool {
+        file_modified(&data_path()) != self.file_mtime.get()
+    }
+}
+
+fn file_modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+/// How often each menu action was chosen. Only action names and counts are
+/// kept, never what was borrowed or searched for.
+#[derive(Serialize, Deserialize, Default)]
+struct UsageStats {
+    counts: BTreeMap<String, u64>,
+}
+
+impl UsageStats {
+    fn record(&mut self, menu: &str, items: &[&str], choice: &str) {
+        let Some(label) = menu_label(items, choice) else {
+            return;
+        };
+        if label == "Back" || label == "Exit" {
+            return;
+        }
+        if menu.is_empty() {
+            self.record_action(label);
+        } else {
+            self.record_action(&format!("{menu} > {label}"));
+        }
+    }
+
+    fn record_action(&mut self, action: &str) {
+        *self.counts.entry(action.to_string()).or_insert(0) += 1;
+    }
+}
+
+fn usage_path() -> PathBuf {
+    data_path().with_file_name(USAGE_FILE)
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum HistoryAction {
+    Borrow,
+    Return,
+    ReturnDamaged,
+    Lost,
+    UndoBorrow,
+    UndoReturn,
+}
+
+impl HistoryAction {
+    fn label(self) -> &'static str {
+        match self {
+            HistoryAction::Borrow => "Borrowed",
+            HistoryAction::Return => "Returned",
+            HistoryAction::ReturnDamaged => "Returned damaged",
+            HistoryAction::Lost => "Lost",
+            HistoryAction::UndoBorrow => "Borrow undone",
+            HistoryAction::UndoReturn => "Return undone",
+        }
+    }
+}
+
+/// One line of the append-only borrow/return log.
+#[derive(Serialize, Deserialize)]
+struct HistoryEntry {
+    at: DateTime<Utc>,
+    book_id: String,
+    action: HistoryAction,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    borrower: Option<String>,
+}
+
+/// Kept beside the data file but never rewritten, so resetting the catalog
+/// leaves the history alone.
+fn history_path() -> PathBuf {
+    data_path().with_file_name(HISTORY_FILE)
+}
+
+fn append_history(entry: &HistoryEntry) -> Result<()> {
+    let mut line = serde_json::to_string(entry)?;
+    line.push('\n');
+    let mut file = fs::OpenOptions::new().create(true).append(true).open(history_path())?;
+    file.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+fn history_entry(book: &Book, action: HistoryAction, borrower: Option<&str>) -> HistoryEntry {
+    HistoryEntry {
+        at: Utc::now(),
+        book_id: book.id.clone(),
+        action,
+        borrower: borrower.map(str::to_string),
+    }
+}
+
+fn record_history(entry: &HistoryEntry) {
+    if let Err(err) = append_history(entry) {
+        eprintln!("Warning: could not write history: {err}");
+    }
+}
+
+/// Reads the history log, skipping lines that do not parse. Returns the
+/// entries and how many lines were skipped.
+fn load_history() -> (Vec<HistoryEntry>, usize) {
+    let Ok(text) = fs::read_to_string(history_path()) else {
+        return (Vec::new(), 0);
+    };
+    let mut skipped = 0;
+    let entries = text
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| {
+            let entry = serde_json::from_str(line).ok();
+            skipped += usize::from(entry.is_none());
+            entry
+        })
+        .collect();
+    (entries, skipped)
+}
+
+fn load_usage_stats() -> UsageStats {
+    fs::read_to_string(usage_path())
+        .ok()
+        .and_then(|text| serde_json::from_str(&text).ok())
+        .unwrap_or_default()
+}
+
+fn save_usage_stats(usage: &UsageStats) -> Result<()> {
+    fs::write(usage_path(), serde_json::to_string_pretty(usage)?)?;
+    Ok(())
 }
 
+static DATA_PATH: OnceLock<PathBuf> = OnceLock::new();
+
 fn data_path() -> PathBuf {
-    PathBuf::from(DATA_FILE)
+    DATA_PATH.get().cloned().unwrap_or_else(|| PathBuf::from(DATA_FILE))
+}
+
+/// Picks the data file from `--data`, then `BOOK_LENDING_DATA`, then the
+/// default file in the working directory.
+fn resolve_data_path(flag: Option<&Path>) -> PathBuf {
+    let home = std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(PathBuf::from);
+    let chosen = flag.map(Path::to_path_buf).or_else(|| {
+        std::env::var_os(DATA_ENV_VAR)
+            .filter(|value| !value.is_empty())
+            .map(PathBuf::from)
+    });
+    match chosen {
+        Some(path) => expand_home(&path, home.as_deref()),
+        None => PathBuf::from(DATA_FILE),
+    }
+}
+
+/// Replaces a leading `~` with the home directory, when one is known.
+fn expand_home(path: &Path, home: Option<&Path>) -> PathBuf {
+    match (path.strip_prefix("~"), home) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// A save refused in shared mode because another process wrote the data file
+/// after we last read or saved it.
+#[derive(Debug)]
+struct ChangedOnDisk;
+
+impl fmt::Display for ChangedOnDisk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the data file was changed by someone else since it was loaded, so this change \
+             was not saved over theirs"
+        )
+    }
+}
+
+impl std::error::Error for ChangedOnDisk {}
+
+/// A data file this build cannot interpret, which it must leave untouched
+/// rather than reset.
+#[derive(Debug)]
+enum FormatError {
+    NewerVersion(u32),
+    BadVersion(serde_json::Value),
+    NotAnObject,
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormatError::NewerVersion(found) => write!(
+                f,
+                "the data file uses format version {found}, but this program only understands \
+                 up to version {DATA_VERSION}; please upgrade book-lending-cli to open it"
+            ),
+            FormatError::BadVersion(found) => write!(
+                f,
+                "the data file has format version {found}, which is not a whole number; \
+                 fix the \"version\" field by hand to open it"
+            ),
+            FormatError::NotAnObject => write!(
+                f,
+                "the data file does not hold a JSON object at the top level, so it is not \
+                 a library file"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+/// Upgrades a parsed data file to `DATA_VERSION` in place and returns the
+/// version it started at.
+fn migrate(data: &mut serde_json::Value) -> Result<u32> {
+    let Some(fields) = data.as_object_mut() else {
+        return Err(FormatError::NotAnObject.into());
+    };
+    let found = match fields.get("version") {
+        None => 1,
+        Some(version) => match version.as_u64().map(u32::try_from) {
+            Some(Ok(found)) => found,
+            // Too large to fit is still a whole number from some future build.
+            Some(Err(_)) => return Err(FormatError::NewerVersion(u32::MAX).into()),
+            None => return Err(FormatError::BadVersion(version.clone()).into()),
+        },
+    };
+    if found > DATA_VERSION {
+        return Err(FormatError::NewerVersion(found).into());
+    }
+    // 1 -> 2: loans were added. Books without them default to none, so only
+    // the version number changes.
+    fields.insert("version".into(), DATA_VERSION.into());
+    Ok(found)
+}
+
+/// Parses a data file of any supported version, returning the library and
+/// the version the file was written in.
+fn parse_versioned_library(text: &str) -> Result<(Library, u32)> {
+    let mut data: serde_json::Value = serde_json::from_str(text)?;
+    let found = migrate(&mut data)?;
+    let mut library: Library = serde_json::from_value(data)?;
+    library.rebuild_index();
+    Ok((library, found))
+}
+
+fn parse_library(text: &str) -> Result<Library> {
+    parse_versioned_library(text).map(|(library, _)| library)
+}
+
+fn read_library_file(path: &Path) -> Result<Library> {
+    let text = fs::read_to_string(path)?;
+    parse_library(&text)
 }
 
 fn default_library() -> Library {
-    Library {
-        books: vec![
-            Book {
-                id: "B001".into(),
-                title: "1984".into(),
-                author: "George Orwell".into(),
-                copies_total: 3,
-                copies_available: 3,
-            },
-            Book {
-                id: "B002".into(),
-                title: "Pride and Prejudice".into(),
-                author: "Jane Austen".into(),
-                copies_total: 2,
-                copies_available: 2,
-            },
-            Book {
-                id: "B003".into(),
-                title: "To Kill a Mockingbird".into(),
-                author: "Harper Lee".into(),
-                copies_total: 4,
-                copies_available: 4,
-            },
-            Book {
-                id: "B004".into(),
-                title: "The Great Gatsby".into(),
-                author: "F. Scott Fitzgerald".into(),
-                copies_total: 2,
-                copies_available: 2,
-            },
-        ],
+    let override_path = data_path().with_file_name(DEFAULTS_OVERRIDE_FILE);
+    if override_path.exists() {
+        match read_library_file(&override_path) {
+            Ok(lib) => return lib,
+            Err(err) => eprintln!(
+                "Warning: could not use {} ({err}). Using built-in defaults.",
+                override_path.display()
+            ),
+        }
     }
+    parse_library(EMBEDDED_DEFAULTS).expect("embedded default catalog is valid")
+}
+
+/// Highest number used by a `B###` id, or 0 when the catalog has none.
+fn max_book_number(library: &Library) -> u32 {
+    library
+        .books
+        .iter()
+        .filter_map(|book| book.id.strip_prefix(['B', 'b'])?.parse::<u32>().ok())
+        .max()
+        .unwrap_or(0)
+}
+
+/// Reserves `count` `B###` numbers above every id in use, or `None` if they
+/// would run past `u32::MAX`.
+fn next_book_numbers(library: &Library, count: usize) -> Option<Range<u32>> {
+    let first = max_book_number(library).checked_add(1)?;
+    let end = first.checked_add(u32::try_from(count).ok()?)?;
+    Some(first..end)
+}
+
+fn format_book_id(number: u32) -> String {
+    format!("B{number:03}")
+}
+
+/// Appends `count` synthetic books for demos and performance testing. Uses a
+/// fixed seed and no timestamps so the same count always produces the same
+/// catalog. Fails without adding anything if the ids would run out.
+fn seed_demo(library: &mut Library, count: usize) -> Result<()> {
+    const ADJECTIVES: &[&str] = &[
+        "Silent", "Crimson", "Hidden", "Last", "Broken", "Golden", "Distant", "Quiet",
+        "Lost", "Burning", "Frozen", "Secret",
+    ];
+    const NOUNS: &[&str] = &[
+        "River", "Garden", "Empire", "Harbor", "Letter", "Forest", "Winter", "Mirror",
+        "Kingdom", "Voyage", "Orchard", "Tower",
+    ];
+    const FIRST_NAMES: &[&str] = &[
+        "Ada", "Boris", "Chloe", "Dmitri", "Elena", "Farid", "Grace", "Hiro", "Imani", "Jonas",
+    ];
+    const LAST_NAMES: &[&str] = &[
+        "Okafor", "Lindqvist", "Moreau", "Tanaka", "Reyes", "Novak", "Achebe", "Kowalski",
+        "Haddad", "Sato",
+    ];
+
+    let numbers = next_book_numbers(library, count)
+        .with_context(|| format!("cannot add {count} demo books: the ids would run out"))?;
+
+    let mut rng = StdRng::seed_from_u64(DEMO_SEED);
+    for number in numbers {
+        let pick = |rng: &mut StdRng, words: &[&str]| -> String {
+            words.choose(rng).copied().unwrap_or_default().to_string()
+        };
+        let title = format!("The {} {}", pick(&mut rng, ADJECTIVES), pick(&mut rng, NOUNS));
+        let author = format!("{} {}", pick(&mut rng, FIRST_NAMES), pick(&mut rng, LAST_NAMES));
+        let copies: u32 = rng.random_range(1..=5);
+        let id = format_book_id(number);
+        library.id_index.insert(id.to_lowercase(), library.books.len());
+        library.books.push(Book {
+            id,
+            title,
+            author,
+            copies_total: copies,
+            copies_available: copies,
+            ..Default::default()
+        });
+    }
+    Ok(())
 }
 
 fn save_data(library: &Library) -> Result<()> {
     let text = serde_json::to_string_pretty(library)?;
-    fs::write(data_path(), text)?;
+    let path = data_path();
+    // Someone else's save would be lost; the menu reloads their copy instead.
+    if library.settings.shared_mode && path.exists() && library.changed_on_disk() {
+        return Err(ChangedOnDisk.into());
+    }
+    if path.exists() {
+        backup_file(&path)?;
+    } else {
+        fs::create_dir_all(parent_dir(&path))?;
+    }
+    write_atomically(&path, &text)?;
+    library.remember_file_state();
+    Ok(())
+}
+
+fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".tmp");
+    PathBuf::from(name)
+}
+
+/// Writes `text` to a temporary file next to `path` and renames it into place,
+/// so an interrupted write leaves the old contents untouched.
+fn write_atomically(path: &Path, text: &str) -> io::Result<()> {
+    let temp = temp_path(path);
+    let mut file = fs::File::create(&temp)?;
+    file.write_all(text.as_bytes())?;
+    file.sync_all()?;
+    drop(file);
+    fs::rename(&temp, path)?;
+    // Persist the rename itself; not every platform can open a directory.
+    if let Ok(dir) = fs::File::open(parent_dir(path)) {
+        let _ = dir.sync_all();
+    }
     Ok(())
 }
 
+/// Copies `path` into the backup directory beside it as
+/// `<stem>-[<label>-]<timestamp>.json` and returns the copy's path.
+fn copy_to_backups(path: &Path, label: Option<&str>) -> io::Result<PathBuf> {
+    let dir = parent_dir(path).join(BACKUP_DIR);
+    fs::create_dir_all(&dir)?;
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let stamp = Local::now().format("%Y%m%d-%H%M%S%.3f");
+    let name = match label {
+        Some(label) => format!("{stem}-{label}-{stamp}.json"),
+        None => format!("{stem}-{stamp}.json"),
+    };
+    let backup = dir.join(name);
+    fs::copy(path, &backup)?;
+    Ok(backup)
+}
+
+/// Keeps a labelled copy of `path` (e.g. a damaged file) that the rotation
+/// in `backup_file` never prunes.
+fn preserve_file(path: &Path, label: &str) -> io::Result<PathBuf> {
+    copy_to_backups(path, Some(label))
+}
+
+/// Copies `path` into the backup directory beside it under a timestamped
+/// name, keeping only the newest `BACKUP_LIMIT` of these rotating copies.
+fn backup_file(path: &Path) -> io::Result<PathBuf> {
+    let backup = copy_to_backups(path, None)?;
+    let dir = parent_dir(&backup);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+
+    // Timestamps sort lexically, so the oldest backups come first. Labelled
+    // copies have a word before the timestamp and are left alone.
+    let prefix = format!("{stem}-");
+    let mut backups: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|candidate| {
+            candidate
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_prefix(&prefix))
+                .is_some_and(|rest| {
+                    rest.starts_with(|c: char| c.is_ascii_digit()) && rest.ends_with(".json")
+                })
+        })
+        .collect();
+    backups.sort();
+    let excess = backups.len().saturating_sub(BACKUP_LIMIT);
+    for old in &backups[..excess] {
+        fs::remove_file(old)?;
+    }
+    Ok(backup)
+}
+
+/// In shared mode, reloads the catalog if another process wrote the data file
+/// since we last read or saved it. Returns whether the catalog was replaced.
+fn reload_shared(library: &mut Library) -> bool {
+    if !library.settings.shared_mode || !library.changed_on_disk() {
+        return false;
+    }
+
+    match read_library_file(&data_path()) {
+        Ok(fresh) => {
+            println!("Note: the data file was changed by someone else; reloaded the latest copy.");
+            for warning in validation_warnings(&fresh) {
+                eprintln!("Warning: {warning}");
+            }
+            fresh.remember_file_state();
+            *library = fresh;
+            true
+        }
+        Err(err) if err.is::<FormatError>() => {
+            eprintln!("Error: {err}. Exiting without saving.");
+            std::process::exit(1);
+        }
+        Err(err) => {
+            eprintln!("Warning: the data file changed but could not be reloaded ({err}).");
+            false
+        }
+    }
+}
+
+/// Like `reload_shared`, then finds the selected book again by ID so the
+/// pending borrow/return is checked against the latest counts.
+fn reload_if_changed(library: &mut Library, book_idx: usize) -> Option<usize> {
+    let id = library.books[book_idx].id.clone();
+    if !reload_shared(library) {
+        return Some(book_idx);
+    }
+    let found = library.find_by_id(&id);
+    if found.is_none() {
+        println!("Book {id} is no longer in the catalog.");
+    }
+    found
+}
+
+/// Writes an upgraded data file back in the current format, first keeping
+/// the old file under a name the backup rotation never prunes. Read-only
+/// runs skip this so they leave the file as they found it.
+fn save_upgrade(library: &mut Library) {
+    let Some(found) = library.upgraded_from.take() else {
+        return;
+    };
+    let saved = preserve_file(&data_path(), &format!("v{found}"))
+        .map_err(anyhow::Error::from)
+        .and_then(|kept| save_data(library).map(|()| kept));
+    match saved {
+        Ok(kept) => eprintln!(
+            "Upgraded the data file from format version {found} to {DATA_VERSION}; \
+             the old file is kept as {}.",
+            kept.display()
+        ),
+        Err(err) => {
+            eprintln!("Warning: could not save the upgraded data: {err}");
+            library.upgraded_from = Some(found);
+        }
+    }
+}
+
 fn load_data() -> Library {
     let path = data_path();
     if !path.exists() {
@@ -78,10 +873,35 @@ fn load_data() -> Library {
     }
 
     match fs::read_to_string(&path) {
-        Ok(content) => match serde_json::from_str::<Library>(&content) {
-            Ok(lib) => lib,
+        Ok(content) => match parse_versioned_library(&content) {
+            Ok((mut lib, found)) => {
+                for warning in validation_warnings(&lib) {
+                    eprintln!("Warning: {warning}");
+                }
+                lib.remember_file_state();
+                lib.upgraded_from = (found < DATA_VERSION).then_some(found);
+                lib
+            }
+            Err(err) if err.is::<FormatError>() => {
+                eprintln!("Error: {err}.");
+                std::process::exit(1);
+            }
             Err(err) => {
-                eprintln!("Data file is corrupted ({err}). Resetting to defaults.");
+                let kept = match preserve_file(&path, "corrupt") {
+                    Ok(kept) => kept,
+                    Err(copy_err) => {
+                        eprintln!(
+                            "Error: the data file is corrupted ({err}) and a copy could not be \
+                             kept ({copy_err}). Leaving it untouched."
+                        );
+                        std::process::exit(1);
+                    }
+                };
+                eprintln!(
+                    "Data file is corrupted ({err}). Resetting to defaults; the damaged file \
+                     is kept as {}.",
+                    kept.display()
+                );
                 let lib = default_library();
                 if let Err(err) = save_data(&lib) {
                     eprintln!("Warning: failed to write default data: {err}");
@@ -100,38 +920,217 @@ fn borrowed_count(book: &Book) -> u32 {
     book.copies_total.saturating_sub(book.copies_available)
 }
 
-fn print_book_table(library: &Library, indices: &[usize], show_available: bool, show_borrowed: bool) {
+fn indices_where(library: &Library, predicate: impl Fn(&Book) -> bool) -> Vec<usize> {
+    library
+        .books
+        .iter()
+        .enumerate()
+        .filter(|(_, book)| predicate(book))
+        .map(|(idx, _)| idx)
+        .collect()
+}
+
+/// Borrowed copies with no recorded borrower, from before loans were tracked.
+fn unrecorded_loans(book: &Book) -> u32 {
+    borrowed_count(book).saturating_sub(book.loans.len() as u32)
+}
+
+fn borrowers_label(book: &Book) -> String {
+    let mut names: Vec<String> = book.loans.iter().map(|loan| loan.borrower.clone()).collect();
+    let unrecorded = unrecorded_loans(book);
+    if unrecorded > 0 {
+        names.push(format!("{unrecorded} unrecorded"));
+    }
+    names.join(", ")
+}
+
+fn is_borrowable(book: &Book) -> bool {
+    book.copies_available > 0 && !book.suspended
+}
+
+fn status_label(book: &Book) -> String {
+    let mut parts = Vec::new();
+    if book.suspended {
+        if book.suspend_reason.is_empty() {
+            parts.push("Suspended".to_string());
+        } else {
+            parts.push(format!("Suspended ({})", book.suspend_reason));
+        }
+    }
+    if book.needs_review {
+        parts.push("Needs review".into());
+    }
+    if parts.is_empty() {
+        "In circulation".into()
+    } else {
+        parts.join(", ")
+    }
+}
+
+/// Optional columns shown after the fixed #/ID/Title/Author columns.
+#[derive(Clone, Copy)]
+struct Columns {
+    available: bool,
+    borrowed: bool,
+    genre: bool,
+    status: bool,
+    updated: bool,
+    loans: bool,
+}
+
+impl Columns {
+    /// Applies display settings that override what a view asked for.
+    fn with_settings(self, settings: &Settings) -> Columns {
+        if settings.show_both_counts {
+            Columns {
+                available: true,
+                borrowed: true,
+                ..self
+            }
+        } else {
+            self
+        }
+    }
+
+    const AVAILABLE: Columns = Columns {
+        available: true,
+        borrowed: false,
+        genre: false,
+        status: false,
+        updated: false,
+        loans: false,
+    };
+    const BORROWED: Columns = Columns {
+        available: false,
+        borrowed: true,
+        genre: false,
+        status: false,
+        updated: false,
+        loans: false,
+    };
+    const LOANS: Columns = Columns {
+        available: false,
+        borrowed: false,
+        genre: false,
+        status: false,
+        updated: false,
+        loans: true,
+    };
+    const COUNTS: Columns = Columns {
+        available: true,
+        borrowed: true,
+        genre: false,
+        status: false,
+        updated: false,
+        loans: false,
+    };
+    const ALL: Columns = Columns {
+        available: true,
+        borrowed: true,
+        genre: true,
+        status: true,
+        updated: false,
+        loans: false,
+    };
+}
+
+fn text_fields(book: &Book) -> [(&'static str, &str); 4] {
+    [
+        ("id", &book.id),
+        ("title", &book.title),
+        ("author", &book.author),
+        ("genre", &book.genre),
+    ]
+}
+
+fn global_search(library: &Library, query: &str) -> Vec<(usize, Vec<&'static str>)> {
+    let lowered = query.to_lowercase();
+    library
+        .books
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, book)| {
+            let matched: Vec<&'static str> = text_fields(book)
+                .iter()
+                .filter(|(_, value)| value.to_lowercase().contains(&lowered))
+                .map(|(name, _)| *name)
+                .collect();
+            (!matched.is_empty()).then_some((idx, matched))
+        })
+        .collect()
+}
+
+fn print_book_table(library: &Library, indices: &[usize], columns: Columns) {
+    for line in book_table_lines(library, indices, columns) {
+        println!("{line}");
+    }
+}
+
+/// Renders the book table as lines so callers other than stdout (like the
+/// raw-mode live filter) can draw it.
+fn book_table_lines(library: &Library, indices: &[usize], columns: Columns) -> Vec<String> {
     if indices.is_empty() {
-        println!("No books to display.");
-        return;
+        return vec!["No books to display.".into()];
     }
+    let columns = columns.with_settings(&library.settings);
 
-    let mut headers = vec!["#".to_string(), "ID".into(), "Title".into(), "Author".into()];
-    if show_available {
+    let numbering = library.settings.numbering;
+    let mut headers = numbering.headers();
+    headers.extend(["ID".into(), "Title".into(), "Author".into()]);
+    if columns.available {
         headers.push("Available".into());
     }
-    if show_borrowed {
+    if columns.borrowed {
         headers.push("Borrowed".into());
     }
+    if columns.genre {
+        headers.push("Genre".into());
+    }
+    if columns.status {
+        headers.push("Status".into());
+    }
+    if columns.updated {
+        headers.push("Updated".into());
+    }
+    if columns.loans {
+        headers.extend(["Borrower".into(), "Due".into()]);
+    }
 
     let mut rows: Vec<Vec<String>> = Vec::new();
     for (display_idx, book_index) in indices.iter().enumerate() {
         let book = &library.books[*book_index];
-        let mut row = vec![
-            (display_idx + 1).to_string(),
-            book.id.clone(),
-            book.title.clone(),
-            book.author.clone(),
-        ];
-        if show_available {
+        let mut row = numbering.labels(display_idx, *book_index);
+        row.extend([book.id.clone(), book.title.clone(), book.author.clone()]);
+        if columns.available {
             row.push(book.copies_available.to_string());
         }
-        if show_borrowed {
+        if columns.borrowed {
             row.push(borrowed_count(book).to_string());
         }
+        if columns.genre {
+            row.push(book.genre.clone());
+        }
+        if columns.status {
+            row.push(status_label(book));
+        }
+        if columns.updated {
+            row.push(
+                book.updated_at
+                    .map(|at| at.with_timezone(&Local).format("%Y-%m-%d %H:%M").to_string())
+                    .unwrap_or_else(|| "-".into()),
+            );
+        }
+        if columns.loans {
+            row.push(borrowers_label(book));
+            let due: Vec<String> = book.loans.iter().map(|loan| loan.due.to_string()).collect();
+            row.push(due.join(", "));
+        }
         rows.push(row);
     }
+    format_table(&headers, &rows)
+}
 
+fn format_table(headers: &[String], rows: &[Vec<String>]) -> Vec<String> {
     let col_widths: Vec<usize> = headers
         .iter()
         .enumerate()
@@ -152,46 +1151,232 @@ fn print_book_table(library: &Library, indices: &[usize], show_available: bool,
             .join(" | ")
     };
 
-    println!("{}", fmt_row(&headers));
     let divider = col_widths
         .iter()
         .map(|w| "-".repeat(*w))
         .collect::<Vec<_>>()
         .join("-+-");
-    println!("{divider}");
-    for row in rows {
-        println!("{}", fmt_row(&row));
-    }
+    let mut lines = vec![fmt_row(headers), divider];
+    lines.extend(rows.iter().map(|row| fmt_row(row)));
+    lines
 }
 
-fn read_choice(prompt: &str) -> Option<String> {
-    print!("{prompt}");
-    let _ = io::stdout().flush();
-    let mut input = String::new();
-    if io::stdin().read_line(&mut input).is_err() {
-        return None;
-    }
-    Some(input.trim().to_string())
+fn terminal_width() -> usize {
+    terminal_size::terminal_size()
+        .map(|(terminal_size::Width(w), _)| w as usize)
+        .unwrap_or(DEFAULT_TERMINAL_WIDTH)
 }
 
-fn select_book_index(library: &Library, indices: &[usize], prompt: &str) -> Option<usize> {
-    let input = read_choice(prompt)?;
+fn terminal_height() -> usize {
+    terminal_size::terminal_size()
+        .map(|(_, terminal_size::Height(h))| h as usize)
+        .unwrap_or(DEFAULT_TERMINAL_HEIGHT)
+}
+
+fn print_book_grid(library: &Library, indices: &[usize]) {
+    if indices.is_empty() {
+        println!("No books to display.");
+        return;
+    }
+
+    let cells: Vec<String> = indices
+        .iter()
+        .enumerate()
+        .map(|(display_idx, book_index)| {
+            let book = &library.books[*book_index];
+            let number = library.settings.numbering.labels(display_idx, *book_index).join("/");
+            format!("{}. {} {}", number, book.id, book.title)
+        })
+        .collect();
+
+    let cell_width = cells.iter().map(|c| c.chars().count()).max().unwrap_or(0);
+    let gap = 2;
+    let columns = ((terminal_width() + gap) / (cell_width + gap)).max(1);
+    let rows = cells.len().div_ceil(columns);
+
+    // Fill column by column, like `ls`, so numbering reads top to bottom.
+    for row in 0..rows {
+        let line = (0..columns)
+            .filter_map(|col| cells.get(col * rows + row))
+            .map(|cell| format!("{:<width$}", cell, width = cell_width))
+            .collect::<Vec<_>>()
+            .join(&" ".repeat(gap));
+        println!("{}", line.trim_end());
+    }
+}
+
+fn print_book_lines(library: &Library, indices: &[usize], columns: Columns) {
+    if indices.is_empty() {
+        println!("No books to display.");
+        return;
+    }
+    let columns = columns.with_settings(&library.settings);
+
+    for (display_idx, book_index) in indices.iter().enumerate() {
+        let book = &library.books[*book_index];
+        let number = library.settings.numbering.labels(display_idx, *book_index).join("/");
+        let mut details = Vec::new();
+        if columns.available {
+            details.push(format!("{} available", book.copies_available));
+        }
+        if columns.borrowed {
+            details.push(format!("{} borrowed", borrowed_count(book)));
+        }
+        if columns.status && (book.suspended || book.needs_review) {
+            details.push(status_label(book));
+        }
+        if columns.loans {
+            details.extend(
+                book.loans
+                    .iter()
+                    .map(|loan| format!("{} due {}", loan.borrower, loan.due)),
+            );
+            let unrecorded = unrecorded_loans(book);
+            if unrecorded > 0 {
+                details.push(format!("{unrecorded} unrecorded"));
+            }
+        }
+        let suffix = if details.is_empty() {
+            String::new()
+        } else {
+            format!(" [{}]", details.join(", "))
+        };
+        println!("{number}. {} {} - {}{suffix}", book.id, book.title, book.author);
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum SortKey {
+    Title,
+    Author,
+    Available,
+    Id,
+}
+
+impl SortKey {
+    const ALL: [(SortKey, &'static str); 4] = [
+        (SortKey::Title, "title"),
+        (SortKey::Author, "author"),
+        (SortKey::Available, "available"),
+        (SortKey::Id, "id"),
+    ];
+
+    fn name(self) -> &'static str {
+        SortKey::ALL
+            .iter()
+            .find(|(key, _)| *key == self)
+            .map_or("", |(_, name)| name)
+    }
+
+    /// The key whose name starts with `input`, ignoring case.
+    fn from_prefix(input: &str) -> Option<SortKey> {
+        let input = input.to_lowercase();
+        SortKey::ALL
+            .iter()
+            .find(|(_, name)| !input.is_empty() && name.starts_with(&input))
+            .map(|(key, _)| *key)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct SortOrder {
+    key: SortKey,
+    descending: bool,
+}
+
+/// Orders `indices` by `order`, comparing text case-insensitively. Ties are
+/// always broken by ascending id so the result is deterministic.
+fn sort_indices(library: &Library, indices: &mut [usize], order: SortOrder) {
+    let books = &library.books;
+    indices.sort_by(|&a, &b| {
+        let (a, b) = (&books[a], &books[b]);
+        let by_id = || a.id.to_lowercase().cmp(&b.id.to_lowercase());
+        let primary = match order.key {
+            SortKey::Title => a.title.to_lowercase().cmp(&b.title.to_lowercase()),
+            SortKey::Author => a.author.to_lowercase().cmp(&b.author.to_lowercase()),
+            SortKey::Available => a.copies_available.cmp(&b.copies_available),
+            SortKey::Id => by_id(),
+        };
+        let primary = if order.descending { primary.reverse() } else { primary };
+        primary.then_with(by_id)
+    });
+}
+
+/// Applies the session sort, if any, to a listing that is about to be shown.
+fn sorted(library: &Library, mut indices: Vec<usize>, options: &Options) -> Vec<usize> {
+    if let Some(order) = options.sort {
+        sort_indices(library, &mut indices, order);
+    }
+    indices
+}
+
+fn compact_listings(library: &Library, options: &Options) -> bool {
+    options.compact.unwrap_or(library.settings.compact_listings)
+}
+
+fn print_books(library: &Library, indices: &[usize], columns: Columns, options: &Options) {
+    if options.grid {
+        print_book_grid(library, indices);
+    } else if compact_listings(library, options) {
+        print_book_lines(library, indices, columns);
+    } else {
+        print_book_table(library, indices, columns);
+    }
+}
+
+fn print_menu(title: &str, items: &[&str]) {
+    println!("\n{title}");
+    for (idx, item) in items.iter().enumerate() {
+        println!("{}) {item}", idx + 1);
+    }
+}
+
+fn menu_label<'a>(items: &[&'a str], choice: &str) -> Option<&'a str> {
+    let idx = choice.parse::<usize>().ok()?.checked_sub(1)?;
+    items.get(idx).copied()
+}
+
+fn read_choice(prompt: &str) -> Option<String> {
+    print!("{prompt}");
+    let _ = io::stdout().flush();
+    let mut input = String::new();
+    if io::stdin().read_line(&mut input).is_err() {
+        return None;
+    }
+    Some(input.trim().to_string())
+}
+
+fn confirm(prompt: &str) -> bool {
+    matches!(
+        read_choice(prompt).map(|answer| answer.to_lowercase()).as_deref(),
+        Some("y") | Some("yes")
+    )
+}
+
+fn select_book_index(library: &Library, indices: &[usize], prompt: &str) -> Option<usize> {
+    let input = read_choice(prompt)?;
     if input.is_empty() || input.eq_ignore_ascii_case("q") {
         return None;
     }
 
     if let Ok(num) = input.parse::<usize>() {
-        if num >= 1 && num <= indices.len() {
-            return Some(indices[num - 1]);
+        let selected = match library.settings.numbering {
+            Numbering::Catalog => num
+                .checked_sub(1)
+                .filter(|book_idx| indices.contains(book_idx)),
+            Numbering::Sequential | Numbering::Both => {
+                num.checked_sub(1).and_then(|pos| indices.get(pos).copied())
+            }
+        };
+        if selected.is_none() {
+            println!("Invalid selection.");
         }
-        println!("Invalid selection.");
-        return None;
+        return selected;
     }
 
-    let lowered = input.to_lowercase();
-    for idx in indices {
-        if library.books[*idx].id.to_lowercase() == lowered {
-            return Some(*idx);
+    if let Some(idx) = library.find_by_id(&input) {
+        if indices.contains(&idx) {
+            return Some(idx);
         }
     }
 
@@ -199,38 +1384,201 @@ fn select_book_index(library: &Library, indices: &[usize], prompt: &str) -> Opti
     None
 }
 
-fn view_available(library: &Library) {
-    let available_indices: Vec<usize> = library
-        .books
-        .iter()
-        .enumerate()
-        .filter(|(_, book)| book.copies_available > 0)
-        .map(|(idx, _)| idx)
-        .collect();
+fn view_available(library: &Library, options: &Options) {
+    let available_indices = sorted(library, indices_where(library, is_borrowable), options);
     println!("\nAvailable books:");
-    print_book_table(library, &available_indices, true, false);
+    print_books(library, &available_indices, Columns::AVAILABLE, options);
 }
 
-fn view_borrowed(library: &Library) {
-    let borrowed_indices: Vec<usize> = library
-        .books
-        .iter()
-        .enumerate()
-        .filter(|(_, book)| borrowed_count(book) > 0)
-        .map(|(idx, _)| idx)
-        .collect();
+fn view_borrowed(library: &Library, options: &Options) {
+    let borrowed = indices_where(library, |book| borrowed_count(book) > 0);
+    let borrowed_indices = sorted(library, borrowed, options);
     println!("\nCurrently borrowed books:");
-    print_book_table(library, &borrowed_indices, false, true);
+    print_books(library, &borrowed_indices, Columns::LOANS, options);
 }
 
-fn borrow_book(library: &mut Library) {
-    let available_indices: Vec<usize> = library
-        .books
-        .iter()
-        .enumerate()
-        .filter(|(_, book)| book.copies_available > 0)
-        .map(|(idx, _)| idx)
-        .collect();
+/// Status keywords accepted by the availability filter, with a short description.
+const STATUS_FILTERS: &[(&str, &str)] = &[
+    ("available", "at least one copy on the shelf"),
+    ("all-out", "every copy is borrowed"),
+    ("some-out", "some, but not all, copies are borrowed"),
+    ("full", "no copies are borrowed"),
+];
+
+fn status_predicate(keyword: &str) -> Option<fn(&Book) -> bool> {
+    let predicate: fn(&Book) -> bool = match keyword {
+        "available" => |book| book.copies_available > 0,
+        "all-out" => |book| book.copies_available == 0,
+        "some-out" => |book| book.copies_available > 0 && borrowed_count(book) > 0,
+        "full" => |book| borrowed_count(book) == 0,
+        _ => return None,
+    };
+    Some(predicate)
+}
+
+fn filter_by_status(library: &Library, options: &Options) {
+    println!("\nStatus keywords:");
+    for (keyword, description) in STATUS_FILTERS {
+        println!("  {keyword:<10} {description}");
+    }
+    let Some(keyword) = read_choice("Filter by status: ") else {
+        return;
+    };
+    let keyword = keyword.to_lowercase();
+    let Some(predicate) = status_predicate(&keyword) else {
+        println!("Unknown status \"{keyword}\".");
+        return;
+    };
+
+    let indices = sorted(library, indices_where(library, predicate), options);
+    println!("\nBooks matching \"{keyword}\":");
+    print_books(library, &indices, Columns::COUNTS, options);
+}
+
+fn title_or_author_matches(library: &Library, query: &str) -> Vec<usize> {
+    let lowered = query.to_lowercase();
+    indices_where(library, |book| {
+        book.title.to_lowercase().contains(&lowered)
+            || book.author.to_lowercase().contains(&lowered)
+    })
+}
+
+/// Books whose id, title, or author contains `query`, ignoring case and
+/// surrounding whitespace. An empty query matches every book.
+fn search_books(library: &Library, query: &str) -> Vec<usize> {
+    let lowered = query.trim().to_lowercase();
+    indices_where(library, |book| {
+        book.id.to_lowercase().contains(&lowered)
+            || book.title.to_lowercase().contains(&lowered)
+            || book.author.to_lowercase().contains(&lowered)
+    })
+}
+
+fn search_and_borrow(library: &mut Library, options: &Options, undo: &mut Vec<UndoEntry>) {
+    let Some(query) = read_choice("\nSearch by id, title, or author (Enter for all): ") else {
+        return;
+    };
+    let indices = sorted(library, search_books(library, &query), options);
+    if indices.is_empty() {
+        println!("No books match \"{}\".", query.trim());
+        return;
+    }
+
+    println!("\nFound {} matching book(s):", indices.len());
+    print_books(library, &indices, Columns::AVAILABLE, options);
+    if checkout_limit_reached(library) {
+        print_checkout_limit_reached(library);
+        return;
+    }
+    if let Some(book_idx) =
+        select_book_index(library, &indices, "\nEnter # or ID to borrow (or press Enter to skip): ")
+    {
+        borrow_selected(library, book_idx, undo);
+    }
+}
+
+fn draw_live_filter(library: &Library, query: &str) -> io::Result<()> {
+    let mut stdout = io::stdout();
+    queue!(stdout, cursor::MoveTo(0, 0), terminal::Clear(ClearType::All))?;
+
+    let indices = title_or_author_matches(library, query);
+    // Leave room for the prompt line and a footer.
+    let max_lines = terminal_height().saturating_sub(3).max(3);
+    let mut lines = book_table_lines(library, &indices, Columns::AVAILABLE);
+    let hidden = lines.len().saturating_sub(max_lines);
+    lines.truncate(max_lines);
+
+    write!(stdout, "Type to filter by title or author (Enter or Esc to finish)\r\n")?;
+    for line in lines {
+        write!(stdout, "{line}\r\n")?;
+    }
+    if hidden > 0 {
+        write!(stdout, "... {hidden} more\r\n")?;
+    }
+    write!(stdout, "Filter: {query}")?;
+    stdout.flush()
+}
+
+/// Runs the keystroke loop; the caller is responsible for raw mode.
+fn run_live_filter(library: &Library) -> io::Result<String> {
+    let mut query = String::new();
+    loop {
+        draw_live_filter(library, &query)?;
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        match key.code {
+            KeyCode::Enter | KeyCode::Esc => return Ok(query),
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                return Ok(query)
+            }
+            KeyCode::Backspace => {
+                query.pop();
+            }
+            KeyCode::Char(c) => query.push(c),
+            _ => {}
+        }
+    }
+}
+
+fn quick_filter(library: &Library, options: &Options) {
+    let live = io::stdin().is_terminal() && terminal::enable_raw_mode().is_ok();
+    let query = if live {
+        let result = run_live_filter(library);
+        let _ = terminal::disable_raw_mode();
+        println!();
+        match result {
+            Ok(query) => query,
+            Err(err) => {
+                eprintln!("Live filter failed ({err}).");
+                return;
+            }
+        }
+    } else {
+        match read_choice("\nFilter by title or author: ") {
+            Some(query) => query,
+            None => return,
+        }
+    };
+
+    let indices = sorted(library, title_or_author_matches(library, query.trim()), options);
+    println!("\nBooks matching \"{}\":", query.trim());
+    print_books(library, &indices, Columns::AVAILABLE, options);
+}
+
+fn view_all(library: &Library, options: &Options) {
+    let indices = sorted(library, (0..library.books.len()).collect(), options);
+    println!("\nAll books:");
+    print_books(library, &indices, Columns::ALL, options);
+}
+
+fn outstanding_checkouts(library: &Library) -> u32 {
+    library.books.iter().map(borrowed_count).sum()
+}
+
+fn checkout_limit_reached(library: &Library) -> bool {
+    library
+        .settings
+        .checkout_limit
+        .is_some_and(|limit| outstanding_checkouts(library) >= limit)
+}
+
+fn print_checkout_limit_reached(library: &Library) {
+    println!(
+        "\nThe library-wide limit of {} checkouts has been reached. Return a book first.",
+        library.settings.checkout_limit.unwrap_or_default()
+    );
+}
+
+fn borrow_book(library: &mut Library, options: &Options, undo: &mut Vec<UndoEntry>) {
+    if checkout_limit_reached(library) {
+        print_checkout_limit_reached(library);
+        return;
+    }
+    let available_indices = sorted(library, indices_where(library, is_borrowable), options);
 
     if available_indices.is_empty() {
         println!("\nNo books are currently available to borrow.");
@@ -238,38 +1586,187 @@ fn borrow_book(library: &mut Library) {
     }
 
     println!("\nSelect a book to borrow:");
-    print_book_table(library, &available_indices, true, false);
+    print_books(library, &available_indices, Columns::AVAILABLE, options);
     if let Some(book_idx) =
         select_book_index(library, &available_indices, "\nEnter # or ID (or press Enter to cancel): ")
     {
-        let title;
-        {
-            let book = &mut library.books[book_idx];
-            if book.copies_available == 0 {
-                println!("No copies left to borrow.");
-                return;
-            }
-            book.copies_available -= 1;
-            title = book.title.clone();
-        } 
+        borrow_selected(library, book_idx, undo);
+    }
+}
 
-        if let Err(err) = save_data(library) {
+/// Borrows one copy of an already selected book, re-checking the latest counts.
+fn borrow_selected(library: &mut Library, book_idx: usize, undo: &mut Vec<UndoEntry>) {
+    let Some(book_idx) = reload_if_changed(library, book_idx) else {
+        return;
+    };
+    let Some(loan) = read_loan() else {
+        println!("No changes made.");
+        return;
+    };
+    let due = loan.due;
+    let id = library.books[book_idx].id.clone();
+    let borrower = loan.borrower.clone();
+    let entry = UndoEntry::Borrow {
+        book_id: id.clone(),
+        loan: Some(loan.clone()),
+    };
+    let title = match library.borrow_by_id(&id, Some(loan)) {
+        Ok(book) => book.title.clone(),
+        Err(err) => {
+            println!("Cannot borrow: {err}.");
+            return;
+        }
+    };
+
+    if let Err(err) = save_data(library) {
         eprintln!("Warning: could not save data: {err}");
+    }
+
+    undo.push(entry);
+    let book = &library.books[book_idx];
+    record_history(&history_entry(book, HistoryAction::Borrow, Some(&borrower)));
+    println!("You borrowed \"{title}\". It is due back on {due}.");
+}
+
+/// Why a borrow, return, or add was refused.
+#[derive(Debug, PartialEq)]
+enum LendError {
+    UnknownId(String),
+    LimitReached(u32),
+    Suspended(String),
+    NoCopiesLeft(String),
+    AllCopiesIn(String),
+    UnknownBorrower { borrower: String, title: String },
+    BorrowerRequired(String),
+    NoCopies,
+    MissingField(&'static str),
+    IdsExhausted,
+}
+
+impl fmt::Display for LendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LendError::UnknownId(id) => write!(f, "no book with id {id}"),
+            LendError::LimitReached(limit) => {
+                write!(f, "the library-wide limit of {limit} checkouts has been reached")
+            }
+            LendError::Suspended(title) => write!(f, "\"{title}\" is suspended from circulation"),
+            LendError::NoCopiesLeft(title) => {
+                write!(f, "no copies of \"{title}\" are left to borrow")
+            }
+            LendError::AllCopiesIn(title) => {
+                write!(f, "all copies of \"{title}\" are already in the library")
+            }
+            LendError::UnknownBorrower { borrower, title } => {
+                write!(f, "{borrower} has no loan of \"{title}\"")
+            }
+            LendError::BorrowerRequired(title) => write!(
+                f,
+                "\"{title}\" is on loan to several borrowers; name the one returning it"
+            ),
+            LendError::NoCopies => write!(f, "a book needs at least one copy"),
+            LendError::MissingField(field) => write!(f, "a book needs a {field}"),
+            LendError::IdsExhausted => write!(f, "no free book ids are left"),
         }
+    }
+}
 
-        println!("You borrowed \"{}\".", title);
+impl std::error::Error for LendError {}
 
+fn ensure_borrowable(library: &Library, book_idx: usize) -> Result<(), LendError> {
+    let book = &library.books[book_idx];
+    if checkout_limit_reached(library) {
+        return Err(LendError::LimitReached(
+            library.settings.checkout_limit.unwrap_or_default(),
+        ));
+    }
+    if book.suspended {
+        return Err(LendError::Suspended(book.title.clone()));
     }
+    if book.copies_available == 0 {
+        return Err(LendError::NoCopiesLeft(book.title.clone()));
+    }
+    Ok(())
 }
 
-fn return_book(library: &mut Library) {
-    let borrowed_indices: Vec<usize> = library
-        .books
-        .iter()
-        .enumerate()
-        .filter(|(_, book)| borrowed_count(book) > 0)
-        .map(|(idx, _)| idx)
-        .collect();
+fn lend_copy(book: &mut Book, loan: Option<Loan>) {
+    book.copies_available -= 1;
+    book.loans.extend(loan);
+    touch(book);
+}
+
+fn due_date(days: u64) -> Option<NaiveDate> {
+    Local::now().date_naive().checked_add_days(Days::new(days))
+}
+
+fn read_loan() -> Option<Loan> {
+    let borrower = read_choice("Borrower name (or press Enter to cancel): ")?;
+    if borrower.is_empty() {
+        return None;
+    }
+    let days = read_setting("Loan length in days", DEFAULT_LOAN_DAYS as usize, 1)?;
+    let due = due_date(u64::try_from(days).ok()?)?;
+    Some(Loan { borrower, due })
+}
+
+/// Asks which borrower is bringing a copy back. Returns `Some(None)` for a copy
+/// with no recorded borrower, and `None` if the return was cancelled.
+fn read_returning_loan(book: &Book) -> Option<Option<usize>> {
+    if book.loans.is_empty() {
+        return Some(None);
+    }
+    let unrecorded = unrecorded_loans(book) > 0;
+
+    println!("\nBorrowers of \"{}\":", book.title);
+    for (position, loan) in book.loans.iter().enumerate() {
+        println!("{}. {} (due {})", position + 1, loan.borrower, loan.due);
+    }
+    let prompt = if unrecorded {
+        "Who is returning it? Enter # or name (or press Enter for an unrecorded copy): "
+    } else {
+        "Who is returning it? Enter # or name (or press Enter to cancel): "
+    };
+    loop {
+        let input = read_choice(prompt)?;
+        if input.is_empty() {
+            return unrecorded.then_some(None);
+        }
+        let found = match input.parse::<usize>() {
+            Ok(num) => num.checked_sub(1).filter(|pos| *pos < book.loans.len()),
+            Err(_) => book
+                .loans
+                .iter()
+                .position(|loan| loan.borrower.eq_ignore_ascii_case(&input)),
+        };
+        match found {
+            Some(position) => return Some(Some(position)),
+            None => println!("No matching borrower."),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum ReturnCondition {
+    Good,
+    Damaged,
+    Lost,
+}
+
+fn read_return_condition() -> Option<ReturnCondition> {
+    loop {
+        let input = read_choice("Condition of the returned copy (good/damaged/lost) [good]: ")?;
+        match input.to_lowercase().as_str() {
+            "" | "good" => return Some(ReturnCondition::Good),
+            "damaged" => return Some(ReturnCondition::Damaged),
+            "lost" => return Some(ReturnCondition::Lost),
+            _ => println!("Please enter good, damaged, or lost."),
+        }
+    }
+}
+
+fn return_book(library: &mut Library, options: &Options, undo: &mut Vec<UndoEntry>) {
+    let borrowed = indices_where(library, |book| borrowed_count(book) > 0);
+    let borrowed_indices = sorted(library, borrowed, options);
 
     if borrowed_indices.is_empty() {
         println!("\nYou have no borrowed books to return.");
@@ -277,63 +1774,2648 @@ fn return_book(library: &mut Library) {
     }
 
     println!("\nSelect a book to return:");
-    print_book_table(library, &borrowed_indices, false, true);
+    print_books(library, &borrowed_indices, Columns::LOANS, options);
     if let Some(book_idx) =
         select_book_index(library, &borrowed_indices, "\nEnter # or ID (or press Enter to cancel): ")
     {
-        let title;
-    {
-        let book = &mut library.books[book_idx];
-        if book.copies_available >= book.copies_total {
-            println!("All copies are already in the library.");
+        let Some(book_idx) = reload_if_changed(library, book_idx) else {
+            return;
+        };
+        if let Err(err) = ensure_returnable(&library.books[book_idx]) {
+            println!("Cannot return: {err}.");
             return;
         }
-        book.copies_available += 1;
-        title = book.title.clone();
-    } 
+        let Some(loan) = read_returning_loan(&library.books[book_idx]) else {
+            println!("No changes made.");
+            return;
+        };
 
-    if let Err(err) = save_data(library) {
-        eprintln!("Warning: could not save data: {err}");
-    }
+        let condition = if library.settings.prompt_return_condition {
+            match read_return_condition() {
+                Some(condition) => condition,
+                None => return,
+            }
+        } else {
+            ReturnCondition::Good
+        };
 
-println!("Thank you for returning \"{}\".", title);
+        let book = &library.books[book_idx];
+        let id = book.id.clone();
+        let was_flagged = book.needs_review;
+        let borrower = loan.map(|position| book.loans[position].borrower.clone());
+        let (title, ended) = match library.return_by_id(&id, borrower.as_deref(), condition) {
+            Ok((book, ended)) => (book.title.clone(), ended),
+            Err(err) => {
+                println!("Cannot return: {err}.");
+                return;
+            }
+        };
+        undo.push(UndoEntry::Return {
+            book_id: id,
+            loan: ended,
+            condition,
+            was_flagged,
+        });
+
+        if let Err(err) = save_data(library) {
+            eprintln!("Warning: could not save data: {err}");
+        }
+        let action = match condition {
+            ReturnCondition::Good => HistoryAction::Return,
+            ReturnCondition::Damaged => HistoryAction::ReturnDamaged,
+            ReturnCondition::Lost => HistoryAction::Lost,
+        };
+        record_history(&history_entry(&library.books[book_idx], action, borrower.as_deref()));
 
+        match condition {
+            ReturnCondition::Good => println!("Thank you for returning \"{}\".", title),
+            ReturnCondition::Damaged => {
+                println!("Returned \"{title}\" and flagged it for review.")
+            }
+            ReturnCondition::Lost => println!("Recorded a lost copy of \"{title}\"."),
+        }
     }
 }
 
-fn menu() -> Option<String> {
-    println!(
-        "\nLibrary Menu
-1) View available books
-2) View borrowed books
-3) Borrow a book
-4) Return a book
-5) Exit"
-    );
-    read_choice("Choose an option: ")
+fn ensure_returnable(book: &Book) -> Result<(), LendError> {
+    if book.copies_available >= book.copies_total {
+        return Err(LendError::AllCopiesIn(book.title.clone()));
+    }
+    Ok(())
 }
 
-fn main() {
-    let mut library = load_data();
-
-    loop {
-        match menu().as_deref() {
-            Some("1") => view_available(&library),
-            Some("2") => view_borrowed(&library),
-            Some("3") => borrow_book(&mut library),
-            Some("4") => return_book(&mut library),
-            Some("5") => {
-                println!("Goodbye!");
-                break;
-            }
-            Some(_) => println!("Please choose a valid option (1-5)."),
-            None => {
-                println!("Input error. Exiting.");
-                break;
-            }
+/// Ends the loan at `loan` (if the borrower is known) and puts the copy back.
+fn take_back(book: &mut Book, loan: Option<usize>, condition: ReturnCondition) {
+    if let Some(position) = loan {
+        book.loans.remove(position);
+    }
+    match condition {
+        ReturnCondition::Good => book.copies_available += 1,
+        ReturnCondition::Damaged => {
+            book.copies_available += 1;
+            book.needs_review = true;
         }
-
-        let _ = read_choice("\nPress Enter to continue...");
+        // The copy never comes back, so the library simply owns one fewer.
+        ReturnCondition::Lost => book.copies_total -= 1,
     }
+    touch(book);
 }
 
+/// Catalog changes with no I/O of their own; callers print and save.
+impl Library {
+    /// Borrows one copy of the book with `id`. `loan` is `None` when the
+    /// borrower is not known.
+    fn borrow_by_id(&mut self, id: &str, loan: Option<Loan>) -> Result<&Book, LendError> {
+        let book_idx = self.find_by_id(id).ok_or_else(|| LendError::UnknownId(id.into()))?;
+        ensure_borrowable(self, book_idx)?;
+        lend_copy(&mut self.books[book_idx], loan);
+        Ok(&self.books[book_idx])
+    }
+
+    /// Returns one copy of the book with `id` in good condition, along with
+    /// the loan it ended. Without a `borrower`, an unrecorded copy or the only
+    /// loan is used.
+    fn return_by_id(
+        &mut self,
+        id: &str,
+        borrower: Option<&str>,
+        condition: ReturnCondition,
+    ) -> Result<(&Book, Option<Loan>), LendError> {
+        let book_idx = self.find_by_id(id).ok_or_else(|| LendError::UnknownId(id.into()))?;
+        let book = &self.books[book_idx];
+        ensure_returnable(book)?;
+        let loan = match borrower {
+            Some(name) => Some(
+                book.loans
+                    .iter()
+                    .position(|loan| loan.borrower.eq_ignore_ascii_case(name))
+                    .ok_or_else(|| LendError::UnknownBorrower {
+                        borrower: name.into(),
+                        title: book.title.clone(),
+                    })?,
+            ),
+            None if unrecorded_loans(book) > 0 => None,
+            None if book.loans.len() == 1 => Some(0),
+            None => return Err(LendError::BorrowerRequired(book.title.clone())),
+        };
+        let ended = loan.map(|position| book.loans[position].clone());
+        take_back(&mut self.books[book_idx], loan, condition);
+        Ok((&self.books[book_idx], ended))
+    }
+
+    /// Appends a book under the next free `B###` id.
+    fn add_book(
+        &mut self,
+        title: String,
+        author: String,
+        copies: u32,
+        replacement_cost: f64,
+    ) -> Result<&Book, LendError> {
+        let (title, author) = (title.trim(), author.trim());
+        if title.is_empty() {
+            return Err(LendError::MissingField("title"));
+        }
+        if author.is_empty() {
+            return Err(LendError::MissingField("author"));
+        }
+        if copies == 0 {
+            return Err(LendError::NoCopies);
+        }
+        let number = next_book_numbers(self, 1).ok_or(LendError::IdsExhausted)?.start;
+        let id = format_book_id(number);
+        self.id_index.insert(id.to_lowercase(), self.books.len());
+        self.books.push(Book {
+            id,
+            title: title.to_string(),
+            author: author.to_string(),
+            copies_total: copies,
+            copies_available: copies,
+            replacement_cost,
+            updated_at: Some(Utc::now()),
+            ..Default::default()
+        });
+        Ok(&self.books[self.books.len() - 1])
+    }
+}
+
+/// A borrow or return made this session, with what is needed to reverse it.
+enum UndoEntry {
+    Borrow {
+        book_id: String,
+        loan: Option<Loan>,
+    },
+    Return {
+        book_id: String,
+        loan: Option<Loan>,
+        condition: ReturnCondition,
+        was_flagged: bool,
+    },
+}
+
+impl UndoEntry {
+    fn book_id(&self) -> &str {
+        match self {
+            UndoEntry::Borrow { book_id, .. } | UndoEntry::Return { book_id, .. } => book_id,
+        }
+    }
+
+    fn loan(&self) -> Option<&Loan> {
+        match self {
+            UndoEntry::Borrow { loan, .. } | UndoEntry::Return { loan, .. } => loan.as_ref(),
+        }
+    }
+}
+
+/// Reverses `entry` on its book, or explains why it no longer can.
+fn revert(library: &mut Library, entry: &UndoEntry) -> Result<usize, String> {
+    let book_id = entry.book_id();
+    let book_idx = library
+        .find_by_id(book_id)
+        .ok_or_else(|| format!("book {book_id} is no longer in the catalog"))?;
+    let book = &mut library.books[book_idx];
+    match entry {
+        UndoEntry::Borrow { loan, .. } => {
+            if book.copies_available >= book.copies_total {
+                return Err(format!("every copy of \"{}\" is already back", book.title));
+            }
+            if let Some(loan) = loan {
+                let Some(position) = book.loans.iter().rposition(|other| other == loan) else {
+                    return Err(format!("the loan to {} is no longer on record", loan.borrower));
+                };
+                book.loans.remove(position);
+            }
+            book.copies_available += 1;
+        }
+        UndoEntry::Return { loan, condition, was_flagged, .. } => {
+            match condition {
+                ReturnCondition::Good | ReturnCondition::Damaged => {
+                    if book.copies_available == 0 {
+                        return Err(format!("no copy of \"{}\" is on the shelf", book.title));
+                    }
+                    book.copies_available -= 1;
+                    book.needs_review = *was_flagged;
+                }
+                ReturnCondition::Lost => book.copies_total += 1,
+            }
+            book.loans.extend(loan.clone());
+        }
+    }
+    touch(book);
+    Ok(book_idx)
+}
+
+fn undo_last(library: &mut Library, undo: &mut Vec<UndoEntry>) {
+    let Some(entry) = undo.pop() else {
+        println!("\nNothing to undo yet this session.");
+        return;
+    };
+    if let Some(book_idx) = library.find_by_id(entry.book_id()) {
+        // The entry names its book by id, so the returned index is not needed.
+        let _ = reload_if_changed(library, book_idx);
+    }
+    let book_idx = match revert(library, &entry) {
+        Ok(book_idx) => book_idx,
+        Err(reason) => {
+            println!("\nCannot undo: {reason}.");
+            return;
+        }
+    };
+
+    if let Err(err) = save_data(library) {
+        eprintln!("Warning: could not save data: {err}");
+    }
+    let book = &library.books[book_idx];
+    let (action, what) = match &entry {
+        UndoEntry::Borrow { .. } => (HistoryAction::UndoBorrow, "borrow"),
+        UndoEntry::Return { .. } => (HistoryAction::UndoReturn, "return"),
+    };
+    let borrower = entry.loan().map(|loan| loan.borrower.as_str());
+    record_history(&history_entry(book, action, borrower));
+    println!("\nUndid the {what} of \"{}\".", book.title);
+    if !undo.is_empty() {
+        println!("{} more action(s) can be undone.", undo.len());
+    }
+}
+
+fn search_all_fields(library: &Library, options: &Options) {
+    let Some(query) = read_choice("\nSearch for (id, title, author, genre): ") else {
+        return;
+    };
+    if query.is_empty() {
+        println!("Search cancelled.");
+        return;
+    }
+
+    let results = global_search(library, &query);
+    if results.is_empty() {
+        println!("No books match \"{query}\".");
+        return;
+    }
+
+    println!("\nFound {} matching book(s):", results.len());
+    let indices = sorted(library, results.iter().map(|(book_idx, _)| *book_idx).collect(), options);
+    let matched: HashMap<usize, &Vec<&str>> =
+        results.iter().map(|(book_idx, fields)| (*book_idx, fields)).collect();
+    print_books(library, &indices, Columns::AVAILABLE, options);
+    println!("\nMatched on:");
+    for (display_idx, book_idx) in indices.iter().enumerate() {
+        let number = library.settings.numbering.labels(display_idx, *book_idx).join("/");
+        let id = &library.books[*book_idx].id;
+        println!("{number}. {id}: {}", matched[book_idx].join(", "));
+    }
+}
+
+fn books_by_author(library: &Library, author: &str) -> Vec<usize> {
+    let lowered = author.to_lowercase();
+    indices_where(library, |book| book.author.to_lowercase() == lowered)
+}
+
+fn reassign_author(library: &mut Library, indices: &[usize], author: &str) -> usize {
+    for idx in indices {
+        let book = &mut library.books[*idx];
+        book.author = author.to_string();
+        touch(book);
+    }
+    indices.len()
+}
+
+fn rename_author(library: &mut Library, options: &Options) {
+    let Some(current) = read_choice("\nAuthor to rename: ") else {
+        return;
+    };
+    let indices = books_by_author(library, &current);
+    if indices.is_empty() {
+        println!("No books found by \"{current}\".");
+        return;
+    }
+
+    println!("\nBooks by {current}:");
+    print_books(library, &indices, Columns::COUNTS, options);
+
+    let Some(corrected) = read_choice("\nCorrected author name (or press Enter to cancel): ") else {
+        return;
+    };
+    if corrected.is_empty() {
+        println!("Rename cancelled.");
+        return;
+    }
+    if !confirm(&format!(
+        "Rename {} book(s) to \"{corrected}\"? [y/N]: ",
+        indices.len()
+    )) {
+        println!("Rename cancelled.");
+        return;
+    }
+
+    let updated = reassign_author(library, &indices, &corrected);
+    if let Err(err) = save_data(library) {
+        eprintln!("Warning: could not save data: {err}");
+    }
+    println!("Updated {updated} record(s).");
+}
+
+fn merge_authors(library: &mut Library, options: &Options) {
+    let Some(source) = read_choice("\nMerge books from author: ") else {
+        return;
+    };
+    let source_indices = books_by_author(library, &source);
+    if source_indices.is_empty() {
+        println!("No books found by \"{source}\".");
+        return;
+    }
+
+    let Some(target) = read_choice("Into existing author: ") else {
+        return;
+    };
+    let target_indices = books_by_author(library, &target);
+    let Some(&target_idx) = target_indices.first() else {
+        println!("No books found by \"{target}\".");
+        return;
+    };
+    if source.to_lowercase() == target.to_lowercase() {
+        println!("Both names refer to the same author.");
+        return;
+    }
+    // Keep the spelling already used in the catalog rather than what was typed.
+    let target_name = library.books[target_idx].author.clone();
+
+    println!("\nBooks to reassign to {target_name}:");
+    print_books(library, &source_indices, Columns::COUNTS, options);
+    if !confirm(&format!(
+        "\nReassign {} book(s) to \"{target_name}\"? [y/N]: ",
+        source_indices.len()
+    )) {
+        println!("Merge cancelled.");
+        return;
+    }
+
+    let updated = reassign_author(library, &source_indices, &target_name);
+    if let Err(err) = save_data(library) {
+        eprintln!("Warning: could not save data: {err}");
+    }
+    println!("Updated {updated} record(s).");
+}
+
+fn toggle_suspension(library: &mut Library, options: &Options) {
+    let indices: Vec<usize> = (0..library.books.len()).collect();
+    println!("\nSelect a book to suspend or unsuspend:");
+    print_books(library, &indices, Columns::ALL, options);
+    let Some(book_idx) =
+        select_book_index(library, &indices, "\nEnter # or ID (or press Enter to cancel): ")
+    else {
+        return;
+    };
+
+    let title = library.books[book_idx].title.clone();
+    if library.books[book_idx].suspended {
+        if !confirm(&format!("Return \"{title}\" to circulation? [y/N]: ")) {
+            println!("No changes made.");
+            return;
+        }
+        let book = &mut library.books[book_idx];
+        book.suspended = false;
+        book.suspend_reason.clear();
+        touch(book);
+        println!("\"{title}\" is back in circulation.");
+    } else {
+        let Some(reason) = read_choice("Reason for suspending (e.g. repair): ") else {
+            return;
+        };
+        let book = &mut library.books[book_idx];
+        book.suspended = true;
+        book.suspend_reason = reason;
+        touch(book);
+        println!("\"{title}\" is suspended and cannot be borrowed.");
+    }
+
+    if let Err(err) = save_data(library) {
+        eprintln!("Warning: could not save data: {err}");
+    }
+}
+
+fn kpi_line(library: &Library) -> String {
+    let titles = library.books.len();
+    let total: u32 = library.books.iter().map(|book| book.copies_total).sum();
+    let available: u32 = library.books.iter().map(|book| book.copies_available).sum();
+    let percent = if total == 0 {
+        0
+    } else {
+        (available as f64 * 100.0 / total as f64).round() as u32
+    };
+    let mut line =
+        format!("Catalog: {total} copies across {titles} titles, {available} available ({percent}%)");
+    if let Some(limit) = library.settings.checkout_limit {
+        line.push_str(&format!(
+            ", {}/{limit} checkouts used",
+            outstanding_checkouts(library)
+        ));
+    }
+    line
+}
+
+fn view_last_copies(library: &Library, options: &Options) {
+    let last_copies = indices_where(library, |book| book.copies_available == 1);
+    let indices = sorted(library, last_copies, options);
+    println!("\nBooks down to their last copy:");
+    print_books(library, &indices, Columns::COUNTS, options);
+}
+
+fn view_recently_modified(library: &Library, options: &Options) {
+    let Some(limit) = read_setting("\nHow many books", 10, 1) else {
+        return;
+    };
+    // Stable sort: untimestamped records keep their catalog order at the end.
+    let mut indices: Vec<usize> = (0..library.books.len()).collect();
+    indices.sort_by(|a, b| library.books[*b].updated_at.cmp(&library.books[*a].updated_at));
+    indices.truncate(limit);
+
+    println!("\nMost recently modified books:");
+    let columns = Columns {
+        updated: true,
+        ..Columns::COUNTS
+    };
+    print_books(library, &indices, columns, options);
+}
+
+fn view_most_borrowed(library: &Library, options: &Options) {
+    let Some(limit) = read_setting("\nHow many books", 10, 1) else {
+        return;
+    };
+    let mut indices = indices_where(library, |book| borrowed_count(book) > 0);
+    indices.sort_by(|a, b| {
+        let (a, b) = (&library.books[*a], &library.books[*b]);
+        borrowed_count(b)
+            .cmp(&borrowed_count(a))
+            .then_with(|| a.title.to_lowercase().cmp(&b.title.to_lowercase()))
+    });
+    indices.truncate(limit);
+
+    println!("\nMost borrowed right now:");
+    print_books(library, &indices, Columns::BORROWED, options);
+}
+
+fn view_usage_stats(usage: &UsageStats, persisted: bool) {
+    if usage.counts.is_empty() {
+        println!("\nNo menu actions recorded yet.");
+        return;
+    }
+
+    let mut tallies: Vec<(&String, &u64)> = usage.counts.iter().collect();
+    tallies.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+    let width = tallies.iter().map(|(action, _)| action.len()).max().unwrap_or(0);
+
+    println!("\nMenu usage:");
+    for (action, count) in tallies {
+        println!("{action:<width$}  {count}");
+    }
+    if !persisted {
+        println!("\n(Saving usage statistics is off; new counts last for this session only.)");
+    }
+}
+
+fn format_currency(amount: f64) -> String {
+    format!("${amount:.2}")
+}
+
+fn print_inventory_value(library: &Library) {
+    let shelf: f64 = library
+        .books
+        .iter()
+        .map(|book| book.copies_available as f64 * book.replacement_cost)
+        .sum();
+    let at_risk: f64 = library
+        .books
+        .iter()
+        .map(|book| borrowed_count(book) as f64 * book.replacement_cost)
+        .sum();
+    let unpriced = library.books.iter().filter(|book| book.replacement_cost == 0.0).count();
+
+    println!("\nInventory value:");
+    println!("  On the shelf:      {}", format_currency(shelf));
+    println!("  At risk (on loan): {}", format_currency(at_risk));
+    println!("  Total:             {}", format_currency(shelf + at_risk));
+    if unpriced > 0 {
+        println!("({unpriced} title(s) have no replacement cost set.)");
+    }
+
+    let threshold = library.settings.high_value_threshold;
+    let high_value = indices_where(library, |book| {
+        borrowed_count(book) > 0 && book.replacement_cost > threshold
+    });
+    if high_value.is_empty() {
+        return;
+    }
+    println!(
+        "\nHigh-value titles on loan (over {} per copy):",
+        format_currency(threshold)
+    );
+    for idx in high_value {
+        let book = &library.books[idx];
+        println!(
+            "- {} \"{}\": {} borrowed x {} = {}",
+            book.id,
+            book.title,
+            borrowed_count(book),
+            format_currency(book.replacement_cost),
+            format_currency(borrowed_count(book) as f64 * book.replacement_cost)
+        );
+    }
+}
+
+/// Fields the group-by report can summarize on.
+const GROUP_FIELDS: &[&str] = &["author", "genre"];
+
+fn group_value<'a>(book: &'a Book, field: &str) -> &'a str {
+    match field {
+        "genre" => &book.genre,
+        _ => &book.author,
+    }
+}
+
+/// (value, titles, total copies) per distinct value, most titles first.
+fn group_by(library: &Library, field: &str) -> Vec<(String, usize, u32)> {
+    // Group case-insensitively but report the first spelling seen.
+    let mut groups: Vec<(String, usize, u32)> = Vec::new();
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    for book in &library.books {
+        let value = group_value(book, field).trim();
+        let label = if value.is_empty() { "(none)" } else { value };
+        let pos = *positions.entry(label.to_lowercase()).or_insert_with(|| {
+            groups.push((label.to_string(), 0, 0));
+            groups.len() - 1
+        });
+        groups[pos].1 += 1;
+        groups[pos].2 += book.copies_total;
+    }
+    groups.sort_by(|a, b| {
+        b.1.cmp(&a.1)
+            .then_with(|| a.0.to_lowercase().cmp(&b.0.to_lowercase()))
+    });
+    groups
+}
+
+fn print_group_by(library: &Library) {
+    let prompt = format!("\nGroup by ({}): ", GROUP_FIELDS.join(", "));
+    let Some(field) = read_choice(&prompt) else {
+        return;
+    };
+    let field = field.to_lowercase();
+    if !GROUP_FIELDS.contains(&field.as_str()) {
+        println!("Unknown field \"{field}\".");
+        return;
+    }
+
+    let groups = group_by(library, &field);
+    if groups.is_empty() {
+        println!("No books to display.");
+        return;
+    }
+    let header = field[..1].to_uppercase() + &field[1..];
+    let width = groups
+        .iter()
+        .map(|(value, _, _)| value.chars().count())
+        .chain(std::iter::once(header.len()))
+        .max()
+        .unwrap_or(0);
+    println!("\n{header:<width$} | Titles | Copies");
+    println!("{}-+--------+-------", "-".repeat(width));
+    for (value, titles, copies) in groups {
+        println!("{value:<width$} | {titles:<6} | {copies}");
+    }
+}
+
+fn view_titles_with_digits(library: &Library, options: &Options) {
+    let with_digits = indices_where(library, |book| book.title.chars().any(|c| c.is_ascii_digit()));
+    let indices = sorted(library, with_digits, options);
+    println!("\nTitles containing digits:");
+    print_books(library, &indices, Columns::COUNTS, options);
+}
+
+fn view_history(library: &Library) {
+    let (entries, skipped) = load_history();
+    if entries.is_empty() {
+        println!("\nNo borrows or returns recorded yet.");
+        return;
+    }
+    let Some(count) = read_setting("\nHow many recent entries", DEFAULT_HISTORY_ENTRIES, 1)
+    else {
+        return;
+    };
+
+    let headers: Vec<String> =
+        ["When", "ID", "Title", "Action", "Borrower"].map(String::from).to_vec();
+    let rows: Vec<Vec<String>> = entries[entries.len().saturating_sub(count)..]
+        .iter()
+        .map(|entry| {
+            let title = library
+                .find_by_id(&entry.book_id)
+                .map_or("(removed)", |idx| library.books[idx].title.as_str());
+            vec![
+                entry.at.with_timezone(&Local).format("%Y-%m-%d %H:%M").to_string(),
+                entry.book_id.clone(),
+                title.to_string(),
+                entry.action.label().to_string(),
+                entry.borrower.clone().unwrap_or_default(),
+            ]
+        })
+        .collect();
+
+    println!("\nLast {} of {} history entries:", rows.len(), entries.len());
+    for line in format_table(&headers, &rows) {
+        println!("{line}");
+    }
+    if skipped > 0 {
+        println!("({skipped} unreadable line(s) in {} were skipped.)", HISTORY_FILE);
+    }
+}
+
+fn reports_menu(library: &Library, options: &Options, usage: &mut UsageStats) {
+    print_menu("Reports", REPORTS_MENU);
+    let Some(choice) = read_choice("Choose an option: ") else {
+        return;
+    };
+    usage.record("Reports", REPORTS_MENU, &choice);
+    match choice.as_str() {
+        "1" => println!("\n{}", kpi_line(library)),
+        "2" => view_last_copies(library, options),
+        "3" => view_recently_modified(library, options),
+        "4" => view_most_borrowed(library, options),
+        "5" => view_usage_stats(usage, library.settings.persist_usage_stats),
+        "6" => print_inventory_value(library),
+        "7" => print_group_by(library),
+        "8" => view_titles_with_digits(library, options),
+        "9" => view_history(library),
+        _ => {}
+    }
+}
+
+fn truncate_to(text: &str, width: usize) -> String {
+    if text.chars().count() <= width {
+        return text.to_string();
+    }
+    let mut cut: String = text.chars().take(width.saturating_sub(1)).collect();
+    cut.push('.');
+    cut
+}
+
+fn wrap_words(text: &str, width: usize, max_lines: usize) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    let mut current = String::new();
+    for word in text.split_whitespace() {
+        if current.is_empty() {
+            current = word.to_string();
+        } else if current.chars().count() + 1 + word.chars().count() <= width {
+            current.push(' ');
+            current.push_str(word);
+        } else {
+            lines.push(std::mem::take(&mut current));
+            current = word.to_string();
+        }
+    }
+    if !current.is_empty() {
+        lines.push(current);
+    }
+
+    if lines.len() > max_lines {
+        lines.truncate(max_lines);
+        if let Some(last) = lines.last_mut() {
+            // Force the abbreviation marker so a cut-off title is obvious on the tag.
+            *last = truncate_to(&format!("{last}.."), width);
+        }
+    }
+    lines.into_iter().map(|line| truncate_to(&line, width)).collect()
+}
+
+fn spine_label(book: &Book, format: &LabelFormat) -> Vec<String> {
+    let width = format.width;
+    let surname = book.author.split_whitespace().last().unwrap_or("").to_uppercase();
+    let mut lines = vec![truncate_to(&book.id, width), truncate_to(&surname, width)];
+    lines.extend(wrap_words(&book.title, width, format.title_lines.max(1)));
+
+    let border = format!("+{}+", "-".repeat(width + 2));
+    let mut label = vec![border.clone()];
+    label.extend(
+        lines
+            .iter()
+            .map(|line| format!("| {:<width$} |", line, width = width)),
+    );
+    label.push(border);
+    label
+}
+
+fn print_spine_label(library: &Library, options: &Options) {
+    let indices: Vec<usize> = (0..library.books.len()).collect();
+    println!("\nSelect a book to label:");
+    print_books(library, &indices, Columns::AVAILABLE, options);
+    let Some(book_idx) =
+        select_book_index(library, &indices, "\nEnter # or ID (or press Enter to cancel): ")
+    else {
+        return;
+    };
+
+    println!();
+    for line in spine_label(&library.books[book_idx], &library.settings.label) {
+        println!("{line}");
+    }
+}
+
+/// The string handed to external barcode tools for a book: its ID in upper
+/// case, with anything outside the subset of Code 39 used for ids (A-Z, 0-9,
+/// `-`, `.`) replaced by `-`. Start/stop characters are left to the generator.
+fn barcode_payload(book: &Book) -> String {
+    book.id
+        .trim()
+        .to_uppercase()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '.' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect()
+}
+
+fn print_barcode_payload(library: &Library, options: &Options) {
+    let indices: Vec<usize> = (0..library.books.len()).collect();
+    println!("\nSelect a book:");
+    print_books(library, &indices, Columns::AVAILABLE, options);
+    let Some(book_idx) =
+        select_book_index(library, &indices, "\nEnter # or ID (or press Enter to cancel): ")
+    else {
+        return;
+    };
+    println!("\n{}", barcode_payload(&library.books[book_idx]));
+}
+
+fn read_setting(prompt: &str, current: usize, min: usize) -> Option<usize> {
+    loop {
+        let input = read_choice(&format!("{prompt} [{current}]: "))?;
+        if input.is_empty() {
+            return Some(current);
+        }
+        match input.parse::<usize>() {
+            Ok(value) if value >= min => return Some(value),
+            _ => println!("Please enter a whole number of at least {min}."),
+        }
+    }
+}
+
+fn configure_label_format(library: &mut Library) {
+    let current = library.settings.label.clone();
+    let Some(width) = read_setting("\nLabel width", current.width, 4) else {
+        return;
+    };
+    let Some(title_lines) = read_setting("Title lines", current.title_lines, 1) else {
+        return;
+    };
+    library.settings.label = LabelFormat { width, title_lines };
+    if let Err(err) = save_data(library) {
+        eprintln!("Warning: could not save data: {err}");
+    }
+    println!("Label format updated.");
+}
+
+fn configure_numbering(library: &mut Library) {
+    println!("\nRow numbering:");
+    for (idx, numbering) in Numbering::ALL.iter().enumerate() {
+        let marker = if *numbering == library.settings.numbering {
+            " (current)"
+        } else {
+            ""
+        };
+        println!("{}) {}{marker}", idx + 1, numbering.describe());
+    }
+    let Some(choice) = read_choice("Choose a numbering scheme: ") else {
+        return;
+    };
+    let Some(numbering) = choice
+        .parse::<usize>()
+        .ok()
+        .and_then(|num| num.checked_sub(1))
+        .and_then(|idx| Numbering::ALL.get(idx))
+    else {
+        println!("No changes made.");
+        return;
+    };
+
+    library.settings.numbering = *numbering;
+    if let Err(err) = save_data(library) {
+        eprintln!("Warning: could not save data: {err}");
+    }
+    println!("Row numbering set to: {}.", numbering.describe());
+}
+
+fn on_off(enabled: bool) -> &'static str {
+    if enabled {
+        "on"
+    } else {
+        "off"
+    }
+}
+
+fn toggle_return_condition_prompt(library: &mut Library) {
+    let enabled = !library.settings.prompt_return_condition;
+    library.settings.prompt_return_condition = enabled;
+    if let Err(err) = save_data(library) {
+        eprintln!("Warning: could not save data: {err}");
+    }
+    println!("\nReturn condition prompt is now {}.", on_off(enabled));
+}
+
+fn toggle_compact_listings(library: &mut Library, options: &mut Options) {
+    let enabled = !library.settings.compact_listings;
+    library.settings.compact_listings = enabled;
+    // A saved preference replaces whatever was toggled for this session.
+    options.compact = None;
+    if let Err(err) = save_data(library) {
+        eprintln!("Warning: could not save data: {err}");
+    }
+    println!("\nCompact listings are now {} by default.", on_off(enabled));
+}
+
+fn toggle_session_compact(library: &Library, options: &mut Options) {
+    let enabled = !compact_listings(library, options);
+    options.compact = Some(enabled);
+    println!("\nCompact listings {} for this session.", on_off(enabled));
+}
+
+fn choose_sort_order(options: &mut Options) {
+    let current = match options.sort {
+        Some(order) => {
+            let direction = if order.descending { "descending" } else { "ascending" };
+            format!("sorted by {} ({direction})", order.key.name())
+        }
+        None => "in catalog order".into(),
+    };
+    println!("\nListings are {current}.");
+    let Some(input) =
+        read_choice("Sort by title, author, available, or id (Enter for catalog order): ")
+    else {
+        return;
+    };
+    if input.is_empty() {
+        options.sort = None;
+        println!("Listings are back in catalog order for this session.");
+        return;
+    }
+
+    let Some(key) = SortKey::from_prefix(&input) else {
+        println!("Unknown sort key \"{}\".", input.to_lowercase());
+        return;
+    };
+    let descending = confirm("Descending? [y/N]: ");
+    options.sort = Some(SortOrder { key, descending });
+    let direction = if descending { "descending" } else { "ascending" };
+    println!("Listings sorted by {} ({direction}) for this session.", key.name());
+}
+
+fn toggle_usage_persistence(library: &mut Library, usage: &UsageStats) {
+    let enabled = !library.settings.persist_usage_stats;
+    library.settings.persist_usage_stats = enabled;
+    if let Err(err) = save_data(library) {
+        eprintln!("Warning: could not save data: {err}");
+    }
+    if enabled {
+        if let Err(err) = save_usage_stats(usage) {
+            eprintln!("Warning: could not save usage statistics: {err}");
+        }
+    }
+    println!("\nSaving usage statistics is now {}.", on_off(enabled));
+}
+
+fn configure_high_value_threshold(library: &mut Library) {
+    let current = library.settings.high_value_threshold;
+    let prompt = format!("\nHigh-value threshold per copy [{}]: ", format_currency(current));
+    let Some(threshold) = read_amount(&prompt) else {
+        println!("No changes made.");
+        return;
+    };
+    library.settings.high_value_threshold = threshold;
+    if let Err(err) = save_data(library) {
+        eprintln!("Warning: could not save data: {err}");
+    }
+    println!("High-value threshold set to {}.", format_currency(threshold));
+}
+
+fn toggle_show_both_counts(library: &mut Library) {
+    let enabled = !library.settings.show_both_counts;
+    library.settings.show_both_counts = enabled;
+    if let Err(err) = save_data(library) {
+        eprintln!("Warning: could not save data: {err}");
+    }
+    println!("\nAlways showing both count columns is now {}.", on_off(enabled));
+}
+
+fn toggle_shared_mode(library: &mut Library) {
+    let enabled = !library.settings.shared_mode;
+    library.settings.shared_mode = enabled;
+    if let Err(err) = save_data(library) {
+        eprintln!("Warning: could not save data: {err}");
+    }
+    println!("\nShared file mode is now {}.", on_off(enabled));
+}
+
+fn configure_checkout_limit(library: &mut Library) {
+    let current = library.settings.checkout_limit.unwrap_or(0) as usize;
+    let limit = loop {
+        let Some(limit) =
+            read_setting("\nLibrary-wide checkout limit (0 for unlimited)", current, 0)
+        else {
+            return;
+        };
+        match u32::try_from(limit) {
+            Ok(limit) => break limit,
+            Err(_) => println!("Please enter a limit of at most {}.", u32::MAX),
+        }
+    };
+    library.settings.checkout_limit = match limit {
+        0 => None,
+        limit => Some(limit),
+    };
+    if let Err(err) = save_data(library) {
+        eprintln!("Warning: could not save data: {err}");
+    }
+    match library.settings.checkout_limit {
+        Some(limit) => println!(
+            "Checkout limit set to {limit} ({} currently out).",
+            outstanding_checkouts(library)
+        ),
+        None => println!("Checkouts are unlimited."),
+    }
+}
+
+fn settings_menu(library: &mut Library, options: &mut Options, usage: &mut UsageStats) {
+    let states = [
+        String::new(),
+        String::new(),
+        format!(" ({})", on_off(library.settings.prompt_return_condition)),
+        format!(" ({})", on_off(library.settings.compact_listings)),
+        format!(" ({})", on_off(library.settings.persist_usage_stats)),
+        format!(" ({})", format_currency(library.settings.high_value_threshold)),
+        format!(" ({})", on_off(library.settings.show_both_counts)),
+        format!(" ({})", on_off(library.settings.shared_mode)),
+        format!(
+            " ({})",
+            library
+                .settings
+                .checkout_limit
+                .map_or_else(|| "unlimited".to_string(), |limit| limit.to_string())
+        ),
+        String::new(),
+    ];
+    println!("\nSettings");
+    for (idx, (item, state)) in SETTINGS_MENU.iter().zip(&states).enumerate() {
+        println!("{}) {item}{state}", idx + 1);
+    }
+    let Some(choice) = read_choice("Choose an option: ") else {
+        return;
+    };
+    usage.record("Settings", SETTINGS_MENU, &choice);
+    match choice.as_str() {
+        "1" => configure_label_format(library),
+        "2" => configure_numbering(library),
+        "3" => toggle_return_condition_prompt(library),
+        "4" => toggle_compact_listings(library, options),
+        "5" => toggle_usage_persistence(library, usage),
+        "6" => configure_high_value_threshold(library),
+        "7" => toggle_show_both_counts(library),
+        "8" => toggle_shared_mode(library),
+        "9" => configure_checkout_limit(library),
+        _ => {}
+    }
+}
+
+fn overcounted_books(library: &Library) -> Vec<usize> {
+    indices_where(library, |book| book.copies_available > book.copies_total)
+}
+
+fn check_copy_counts(library: &mut Library) {
+    // borrowed_count() clamps this case to zero, so it never shows up in the normal views.
+    let indices = overcounted_books(library);
+    if indices.is_empty() {
+        println!("\nAll copy counts are consistent.");
+        return;
+    }
+
+    println!("\nBooks with more copies available than they own:");
+    for idx in &indices {
+        let book = &library.books[*idx];
+        println!(
+            "- {} \"{}\": {} available but only {} total",
+            book.id, book.title, book.copies_available, book.copies_total
+        );
+    }
+    if !confirm(&format!(
+        "\nSet available copies to the total for {} book(s)? [y/N]: ",
+        indices.len()
+    )) {
+        println!("No changes made.");
+        return;
+    }
+
+    for idx in &indices {
+        let book = &mut library.books[*idx];
+        book.copies_available = book.copies_total;
+        touch(book);
+    }
+    if let Err(err) = save_data(library) {
+        eprintln!("Warning: could not save data: {err}");
+    }
+    println!("Fixed {} record(s).", indices.len());
+}
+
+/// Ids shared by more than one book, with the position of every book using
+/// each one. Ids compare case-insensitively, as they do when selecting.
+fn duplicate_ids(library: &Library) -> Vec<(String, Vec<usize>)> {
+    // Lowercased id -> position in `groups`, which keeps first-seen order.
+    let mut group_of: HashMap<String, usize> = HashMap::new();
+    let mut groups: Vec<(String, Vec<usize>)> = Vec::new();
+    for (idx, book) in library.books.iter().enumerate() {
+        match group_of.entry(book.id.to_ascii_lowercase()) {
+            Entry::Occupied(group) => groups[*group.get()].1.push(idx),
+            Entry::Vacant(slot) => {
+                slot.insert(groups.len());
+                groups.push((book.id.clone(), vec![idx]));
+            }
+        }
+    }
+    groups.retain(|(_, positions)| positions.len() > 1);
+    groups
+}
+
+/// Logical problems that parsing alone does not catch. These are reported on
+/// load rather than reset, since the rest of the catalog is still good.
+fn validation_warnings(library: &Library) -> Vec<String> {
+    let mut warnings = Vec::new();
+    for (id, positions) in duplicate_ids(library) {
+        warnings.push(format!(
+            "id {id} is used by {} books, so only the first can be selected. \
+             Use Catalog maintenance > Fix duplicate ids to repair.",
+            positions.len()
+        ));
+    }
+    let overcounted = overcounted_books(library);
+    if !overcounted.is_empty() {
+        let ids: Vec<&str> =
+            overcounted.iter().map(|idx| library.books[*idx].id.as_str()).collect();
+        warnings.push(format!(
+            "{} ha{} more copies available than in total. \
+             Use Catalog maintenance > Check copy counts to repair.",
+            ids.join(", "),
+            if ids.len() == 1 { "s" } else { "ve" }
+        ));
+    }
+    warnings
+}
+
+fn fix_duplicate_ids(library: &mut Library) {
+    let duplicates = duplicate_ids(library);
+    if duplicates.is_empty() {
+        println!("\nEvery book has a unique id.");
+        return;
+    }
+
+    println!("\nBooks sharing an id:");
+    for (id, positions) in &duplicates {
+        println!("{id}:");
+        for idx in positions {
+            let book = &library.books[*idx];
+            println!("  \"{}\" by {}", book.title, book.author);
+        }
+    }
+    if !confirm("\nKeep the first book for each id and give the others new ids? [y/N]: ") {
+        println!("No changes made.");
+        return;
+    }
+
+    let renumbered = duplicates.iter().map(|(_, positions)| positions.len() - 1).sum();
+    let Some(mut numbers) = next_book_numbers(library, renumbered) else {
+        println!("Cannot renumber: {}.", LendError::IdsExhausted);
+        return;
+    };
+    for (id, positions) in &duplicates {
+        for (idx, number) in positions[1..].iter().zip(&mut numbers) {
+            let book = &mut library.books[*idx];
+            book.id = format_book_id(number);
+            touch(book);
+            println!("{id} \"{}\" is now {}.", book.title, book.id);
+        }
+    }
+    library.rebuild_index();
+    if let Err(err) = save_data(library) {
+        eprintln!("Warning: could not save data: {err}");
+    }
+}
+
+fn trim_in_place(value: &mut String) -> bool {
+    let trimmed = value.trim();
+    if trimmed.len() == value.len() {
+        return false;
+    }
+    *value = trimmed.to_string();
+    true
+}
+
+fn compact_save(library: &mut Library) {
+    let mut trimmed = 0;
+    for book in &mut library.books {
+        for field in [
+            &mut book.id,
+            &mut book.title,
+            &mut book.author,
+            &mut book.genre,
+            &mut book.suspend_reason,
+        ] {
+            if trim_in_place(field) {
+                trimmed += 1;
+            }
+        }
+    }
+    library.rebuild_index();
+
+    // Fields still at their serde defaults are skipped on serialization, so a
+    // plain save also drops them from hand-edited files.
+    match save_data(library) {
+        Ok(()) => println!(
+            "\nRewrote {} with {} book(s); trimmed {trimmed} field(s).",
+            data_path().display(),
+            library.books.len()
+        ),
+        Err(err) => eprintln!("Warning: could not save data: {err}"),
+    }
+}
+
+fn clear_review_flag(library: &mut Library, options: &Options) {
+    let indices = indices_where(library, |book| book.needs_review);
+    if indices.is_empty() {
+        println!("\nNo books are waiting for review.");
+        return;
+    }
+
+    println!("\nBooks flagged for review:");
+    print_books(library, &indices, Columns::ALL, options);
+    let prompt = "\nEnter # or ID to mark reviewed (or press Enter to cancel): ";
+    let Some(book_idx) = select_book_index(library, &indices, prompt) else {
+        return;
+    };
+
+    let book = &mut library.books[book_idx];
+    book.needs_review = false;
+    touch(book);
+    if let Err(err) = save_data(library) {
+        eprintln!("Warning: could not save data: {err}");
+    }
+    println!("\"{}\" marked as reviewed.", library.books[book_idx].title);
+}
+
+fn same_title_and_author(a: &Book, b: &Book) -> bool {
+    a.title.to_lowercase() == b.title.to_lowercase()
+        && a.author.to_lowercase() == b.author.to_lowercase()
+}
+
+/// Why a book from another file cannot be merged as is, if it cannot.
+fn import_problem(book: &Book) -> Option<String> {
+    if book.copies_available > book.copies_total {
+        return Some(format!(
+            "{} has {} copies available but only {} in total",
+            book.id, book.copies_available, book.copies_total
+        ));
+    }
+    let out = borrowed_count(book);
+    if book.loans.len() > out as usize {
+        return Some(format!(
+            "{} has {} recorded loan(s) but only {out} copy(ies) out",
+            book.id,
+            book.loans.len()
+        ));
+    }
+    None
+}
+
+/// Adds books from `source` whose ID and title+author are both new to the
+/// catalog. Existing entries are never modified, and books whose counts
+/// contradict themselves are left out. Returns (added, skipped, rejected).
+fn import_missing(library: &mut Library, source: Library) -> (usize, usize, Vec<String>) {
+    let mut added = 0;
+    let mut skipped = 0;
+    let mut rejected = Vec::new();
+    for mut book in source.books {
+        let present = library.find_by_id(&book.id).is_some()
+            || library.books.iter().any(|existing| same_title_and_author(existing, &book));
+        if present {
+            skipped += 1;
+            continue;
+        }
+        if let Some(problem) = import_problem(&book) {
+            rejected.push(problem);
+            continue;
+        }
+        touch(&mut book);
+        library.id_index.insert(book.id.to_lowercase(), library.books.len());
+        library.books.push(book);
+        added += 1;
+    }
+    (added, skipped, rejected)
+}
+
+fn import_missing_books(library: &mut Library) {
+    let prompt = "\nLibrary file to import from (or press Enter to cancel): ";
+    let Some(path) = read_choice(prompt) else {
+        return;
+    };
+    if path.is_empty() {
+        println!("Import cancelled.");
+        return;
+    }
+    let source = match read_library_file(Path::new(&path)) {
+        Ok(source) => source,
+        Err(err) => {
+            println!("Could not read {path}: {err}");
+            return;
+        }
+    };
+
+    let (added, skipped, rejected) = import_missing(library, source);
+    if added > 0 {
+        if let Err(err) = save_data(library) {
+            eprintln!("Warning: could not save data: {err}");
+        }
+    }
+    println!("Added {added} book(s); skipped {skipped} already in the catalog.");
+    if !rejected.is_empty() {
+        println!("Rejected {} book(s) with inconsistent counts:", rejected.len());
+        for problem in &rejected {
+            println!("  {problem}");
+        }
+    }
+}
+
+/// Replaces every occurrence of `find` in `text`, comparing one character at
+/// a time so lowercasing that changes byte lengths (like "İ") still matches.
+fn replace_case_insensitive(text: &str, find: &str, replacement: &str) -> String {
+    let same = |a: char, b: char| a == b || a.to_lowercase().eq(b.to_lowercase());
+    // Byte length in `text` of a match starting at `start`, if there is one.
+    let match_at = |start: usize| -> Option<usize> {
+        let mut chars = text[start..].char_indices();
+        for wanted in find.chars() {
+            let (_, found) = chars.next()?;
+            if !same(found, wanted) {
+                return None;
+            }
+        }
+        Some(chars.next().map_or(text.len() - start, |(offset, _)| offset))
+    };
+
+    let mut result = String::new();
+    let mut rest = 0;
+    while rest < text.len() {
+        match match_at(rest).filter(|_| !find.is_empty()) {
+            Some(len) => {
+                result.push_str(replacement);
+                rest += len;
+            }
+            None => {
+                let next = text[rest..].chars().next().map_or(1, char::len_utf8);
+                result.push_str(&text[rest..rest + next]);
+                rest += next;
+            }
+        }
+    }
+    result
+}
+
+fn replace_in_titles(library: &mut Library) {
+    let Some(find) = read_choice("\nText to find in titles (or press Enter to cancel): ") else {
+        return;
+    };
+    if find.is_empty() {
+        println!("Replace cancelled.");
+        return;
+    }
+    let Some(replacement) = read_choice("Replace with: ") else {
+        return;
+    };
+    let case_sensitive = confirm("Match case? [y/N]: ");
+
+    let changes: Vec<(usize, String)> = library
+        .books
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, book)| {
+            let updated = if case_sensitive {
+                book.title.replace(&find, &replacement)
+            } else {
+                replace_case_insensitive(&book.title, &find, &replacement)
+            };
+            (updated != book.title).then_some((idx, updated))
+        })
+        .collect();
+    if changes.is_empty() {
+        println!("No titles contain \"{find}\".");
+        return;
+    }
+
+    println!("\nPreview:");
+    for (idx, updated) in &changes {
+        let book = &library.books[*idx];
+        println!("{}: \"{}\" -> \"{updated}\"", book.id, book.title);
+    }
+    if !confirm(&format!("\nApply changes to {} title(s)? [y/N]: ", changes.len())) {
+        println!("Replace cancelled.");
+        return;
+    }
+
+    for (idx, updated) in &changes {
+        let book = &mut library.books[*idx];
+        book.title = updated.clone();
+        touch(book);
+    }
+    if let Err(err) = save_data(library) {
+        eprintln!("Warning: could not save data: {err}");
+    }
+    println!("Changed {} title(s).", changes.len());
+}
+
+/// Reads a non-negative amount; `None` when the input is empty or closed.
+fn read_amount(prompt: &str) -> Option<f64> {
+    loop {
+        let input = read_choice(prompt)?;
+        if input.is_empty() {
+            return None;
+        }
+        match parse_amount(&input) {
+            Some(value) => return Some(value),
+            None => println!("Please enter a non-negative amount, e.g. 12.50."),
+        }
+    }
+}
+
+fn parse_amount(input: &str) -> Option<f64> {
+    let value = input.trim_start_matches('$').parse::<f64>().ok()?;
+    (value.is_finite() && value >= 0.0).then_some(value)
+}
+
+fn set_replacement_cost(library: &mut Library, options: &Options) {
+    let indices: Vec<usize> = (0..library.books.len()).collect();
+    println!("\nSelect a book to price:");
+    print_books(library, &indices, Columns::AVAILABLE, options);
+    let Some(book_idx) =
+        select_book_index(library, &indices, "\nEnter # or ID (or press Enter to cancel): ")
+    else {
+        return;
+    };
+
+    let current = library.books[book_idx].replacement_cost;
+    let prompt = format!("Replacement cost per copy [{}]: ", format_currency(current));
+    let Some(cost) = read_amount(&prompt) else {
+        println!("No changes made.");
+        return;
+    };
+
+    let book = &mut library.books[book_idx];
+    book.replacement_cost = cost;
+    touch(book);
+    if let Err(err) = save_data(library) {
+        eprintln!("Warning: could not save data: {err}");
+    }
+    println!(
+        "Replacement cost for \"{}\" set to {}.",
+        library.books[book_idx].title,
+        format_currency(cost)
+    );
+}
+
+/// How many current loans would be left without a copy if `copies_total`
+/// became `new_total`. Zero means the change is safe.
+fn copy_reduction_shortfall(book: &Book, new_total: u32) -> u32 {
+    borrowed_count(book).saturating_sub(new_total)
+}
+
+fn preview_copy_reduction(library: &Library, options: &Options) {
+    let indices: Vec<usize> = (0..library.books.len()).collect();
+    println!("\nSelect a book to check:");
+    print_books(library, &indices, Columns::COUNTS, options);
+    let Some(book_idx) =
+        select_book_index(library, &indices, "\nEnter # or ID (or press Enter to cancel): ")
+    else {
+        return;
+    };
+
+    let book = &library.books[book_idx];
+    let new_total = loop {
+        let Some(input) = read_choice(&format!("New total copies [{}]: ", book.copies_total)) else {
+            return;
+        };
+        if input.is_empty() {
+            return;
+        }
+        match input.parse::<u32>() {
+            Ok(value) => break value,
+            Err(_) => println!("Please enter a whole number."),
+        }
+    };
+
+    let borrowed = borrowed_count(book);
+    if new_total >= book.copies_total {
+        println!("That is not a reduction; \"{}\" has {} copies.", book.title, book.copies_total);
+        return;
+    }
+    match copy_reduction_shortfall(book, new_total) {
+        0 => println!(
+            "Allowed: \"{}\" can go from {} to {new_total} copies ({borrowed} currently borrowed).",
+            book.title, book.copies_total
+        ),
+        shortfall => println!(
+            "Blocked: {borrowed} copies of \"{}\" are on loan, so {new_total} is {shortfall} short. \
+             Wait for {shortfall} return(s) or keep at least {borrowed} copies.",
+            book.title
+        ),
+    }
+}
+
+/// A copy of the catalog with every copy back on the shelf, for starting a
+/// new branch from the same book list.
+fn clean_catalog(library: &Library) -> Library {
+    let mut clean = library.clone();
+    for book in &mut clean.books {
+        book.copies_available = book.copies_total;
+        book.loans.clear();
+    }
+    clean
+}
+
+/// Resolves `path` so differently spelled paths to the same file compare
+/// equal. A file that does not exist yet resolves through its directory.
+fn canonical_path(path: &Path) -> PathBuf {
+    fs::canonicalize(path)
+        .or_else(|_| {
+            let name = path.file_name().unwrap_or_default();
+            fs::canonicalize(parent_dir(path)).map(|dir| dir.join(name))
+        })
+        .unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn is_data_file(path: &Path) -> bool {
+    canonical_path(path) == canonical_path(&data_path())
+}
+
+fn export_clean_catalog(library: &Library) {
+    let prompt = "\nWrite clean catalog to (or press Enter to cancel): ";
+    let Some(path) = read_choice(prompt) else {
+        return;
+    };
+    if path.is_empty() {
+        println!("Export cancelled.");
+        return;
+    }
+    let path = PathBuf::from(path);
+    if is_data_file(&path) {
+        println!("Refusing to overwrite the live data file; choose another path.");
+        return;
+    }
+    if path.exists() && !confirm(&format!("{} exists. Overwrite? [y/N]: ", path.display())) {
+        println!("Export cancelled.");
+        return;
+    }
+
+    let clean = clean_catalog(library);
+    let result = serde_json::to_string_pretty(&clean)
+        .map_err(anyhow::Error::from)
+        .and_then(|text| fs::write(&path, text).map_err(anyhow::Error::from));
+    match result {
+        Ok(()) => println!("Wrote {} book(s) to {}.", clean.books.len(), path.display()),
+        Err(err) => println!("Could not write {}: {err}", path.display()),
+    }
+}
+
+fn batch_set_genre(library: &mut Library, options: &Options) {
+    let Some(query) = read_choice("\nSearch for books to categorize (id, title, author, genre): ")
+    else {
+        return;
+    };
+    if query.is_empty() {
+        println!("Cancelled.");
+        return;
+    }
+    let indices: Vec<usize> = global_search(library, &query)
+        .into_iter()
+        .map(|(idx, _)| idx)
+        .collect();
+    if indices.is_empty() {
+        println!("No books match \"{query}\".");
+        return;
+    }
+
+    println!("\nMatching books:");
+    print_books(library, &indices, Columns::ALL, options);
+    let Some(genre) = read_choice("\nGenre to set (\"-\" to clear, Enter to cancel): ") else {
+        return;
+    };
+    if genre.is_empty() {
+        println!("Cancelled.");
+        return;
+    }
+    let genre = if genre == "-" { String::new() } else { genre };
+    if !confirm(&format!("Update {} book(s)? [y/N]: ", indices.len())) {
+        println!("Cancelled.");
+        return;
+    }
+
+    for idx in &indices {
+        let book = &mut library.books[*idx];
+        book.genre = genre.clone();
+        touch(book);
+    }
+    if let Err(err) = save_data(library) {
+        eprintln!("Warning: could not save data: {err}");
+    }
+    println!("Updated {} record(s).", indices.len());
+}
+
+fn clean_up_zero_copy_books(library: &mut Library, options: &Options) {
+    let indices = indices_where(library, |book| book.copies_total == 0);
+    if indices.is_empty() {
+        println!("\nEvery book has at least one copy.");
+        return;
+    }
+
+    println!("\nBooks with zero total copies:");
+    print_books(library, &indices, Columns::ALL, options);
+    let Some(action) = read_choice(
+        "\n(d)elete them, (s)uspend them from circulation, or press Enter to leave them: ",
+    ) else {
+        return;
+    };
+
+    match action.to_lowercase().as_str() {
+        "d" | "delete" => {
+            if !confirm(&format!("Permanently delete {} book(s)? [y/N]: ", indices.len())) {
+                println!("No changes made.");
+                return;
+            }
+            let mut position = 0;
+            library.books.retain(|_| {
+                let keep = !indices.contains(&position);
+                position += 1;
+                keep
+            });
+            library.rebuild_index();
+            println!("Deleted {} book(s).", indices.len());
+        }
+        "s" | "suspend" => {
+            for idx in &indices {
+                let book = &mut library.books[*idx];
+                book.suspended = true;
+                book.suspend_reason = "no copies".into();
+                touch(book);
+            }
+            println!("Suspended {} book(s).", indices.len());
+        }
+        _ => {
+            println!("No changes made.");
+            return;
+        }
+    }
+
+    if let Err(err) = save_data(library) {
+        eprintln!("Warning: could not save data: {err}");
+    }
+}
+
+fn read_copy_count(prompt: &str) -> Option<u32> {
+    loop {
+        let input = read_choice(prompt)?;
+        if input.is_empty() {
+            return None;
+        }
+        match input.parse::<u32>() {
+            Ok(copies) if copies > 0 => return Some(copies),
+            _ => println!("Please enter a whole number of copies, at least 1."),
+        }
+    }
+}
+
+fn add_book(library: &mut Library) {
+    println!("\nAdd a book (press Enter at any prompt to cancel):");
+    let Some(title) = read_choice("Title: ").filter(|title| !title.is_empty()) else {
+        println!("No changes made.");
+        return;
+    };
+    let Some(author) = read_choice("Author: ").filter(|author| !author.is_empty()) else {
+        println!("No changes made.");
+        return;
+    };
+    let Some(copies) = read_copy_count("Total copies: ") else {
+        println!("No changes made.");
+        return;
+    };
+    let cost = read_amount("Replacement cost per copy (Enter to leave unpriced): ");
+
+    let added = match library.add_book(title, author, copies, cost.unwrap_or_default()) {
+        Ok(book) => format!("Added \"{}\" as {}.", book.title, book.id),
+        Err(err) => {
+            println!("Cannot add: {err}.");
+            return;
+        }
+    };
+    if let Err(err) = save_data(library) {
+        eprintln!("Warning: could not save data: {err}");
+    }
+    println!("{added}");
+}
+
+fn remove_book(library: &mut Library, options: &Options) {
+    let indices: Vec<usize> = (0..library.books.len()).collect();
+    println!("\nSelect a book to remove:");
+    print_books(library, &indices, Columns::COUNTS, options);
+    let Some(book_idx) =
+        select_book_index(library, &indices, "\nEnter # or ID (or press Enter to cancel): ")
+    else {
+        return;
+    };
+
+    let book = &library.books[book_idx];
+    let borrowed = borrowed_count(book);
+    if borrowed > 0 {
+        println!(
+            "Cannot remove \"{}\": {borrowed} copy(ies) are still on loan. \
+             Return them first.",
+            book.title
+        );
+        return;
+    }
+    if !confirm(&format!("Remove \"{}\" ({}) from the catalog? [y/N]: ", book.title, book.id)) {
+        println!("No changes made.");
+        return;
+    }
+
+    let removed = library.books.remove(book_idx);
+    library.rebuild_index();
+    if let Err(err) = save_data(library) {
+        eprintln!("Warning: could not save data: {err}");
+    }
+    println!("Removed \"{}\" ({}).", removed.title, removed.id);
+}
+
+const CSV_COLUMNS: [&str; 5] = ["id", "title", "author", "copies_total", "copies_available"];
+
+/// One row of the spreadsheet-friendly catalog format.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct CsvBook {
+    id: String,
+    title: String,
+    author: String,
+    copies_total: u32,
+    copies_available: u32,
+    /// Where the row was read from, for error messages.
+    #[serde(skip)]
+    line: u64,
+}
+
+fn write_catalog_csv(library: &Library, writer: impl Write) -> Result<()> {
+    let mut csv = csv::WriterBuilder::new().has_headers(false).from_writer(writer);
+    csv.write_record(CSV_COLUMNS)?;
+    for book in &library.books {
+        csv.serialize(CsvBook {
+            id: book.id.clone(),
+            title: book.title.clone(),
+            author: book.author.clone(),
+            copies_total: book.copies_total,
+            copies_available: book.copies_available,
+            line: 0,
+        })?;
+    }
+    csv.flush()?;
+    Ok(())
+}
+
+/// Parses a CSV catalog. Every problem in the file is reported, not just the
+/// first, so the spreadsheet can be fixed in one pass.
+fn read_catalog_csv(reader: impl io::Read) -> Result<Vec<CsvBook>, Vec<String>> {
+    let mut csv = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(reader);
+    let headers = csv.headers().map_err(|err| vec![err.to_string()])?.clone();
+    // Checked before any rows, so an empty or foreign file can never be
+    // read as an empty catalog and delete every book.
+    if headers.iter().ne(CSV_COLUMNS) {
+        return Err(vec![format!(
+            "line 1: expected the header \"{}\" but found \"{}\"",
+            CSV_COLUMNS.join(","),
+            headers.iter().collect::<Vec<_>>().join(",")
+        )]);
+    }
+    let mut rows = Vec::new();
+    let mut problems = Vec::new();
+    let mut first_line: HashMap<String, u64> = HashMap::new();
+    for record in csv.records() {
+        let record = match record {
+            Ok(record) => record,
+            Err(err) => {
+                problems.push(match err.kind() {
+                    csv::ErrorKind::UnequalLengths { pos, expected_len, len } => format!(
+                        "line {}: expected {expected_len} fields but found {len} \
+                         (quote values that contain commas)",
+                        pos.as_ref().map_or(0, |pos| pos.line())
+                    ),
+                    _ => err.to_string(),
+                });
+                continue;
+            }
+        };
+        let line = record.position().map_or(0, |position| position.line());
+        let row: CsvBook = match record.deserialize(Some(&headers)) {
+            Ok(row) => CsvBook { line, ..row },
+            Err(err) => {
+                problems.push(format!("line {line}: {err}"));
+                continue;
+            }
+        };
+        if row.id.is_empty() {
+            problems.push(format!("line {line}: id is empty"));
+        } else if let Some(first) = first_line.get(&row.id.to_lowercase()) {
+            problems.push(format!("line {line}: id {} is already used on line {first}", row.id));
+        } else {
+            first_line.insert(row.id.to_lowercase(), line);
+        }
+        if row.copies_available > row.copies_total {
+            problems.push(format!(
+                "line {line}: {} has {} available but only {} in total",
+                row.id, row.copies_available, row.copies_total
+            ));
+        }
+        rows.push(row);
+    }
+    if rows.is_empty() && problems.is_empty() {
+        problems.push("the file lists no books".into());
+    }
+    if problems.is_empty() {
+        Ok(rows)
+    } else {
+        Err(problems)
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct CsvImportSummary {
+    changed: usize,
+    added: usize,
+    removed: usize,
+}
+
+/// Rows that would contradict the loans already recorded: fewer copies out
+/// than named borrowers, or a book with copies on loan left out of the file.
+fn catalog_csv_conflicts(library: &Library, rows: &[CsvBook]) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mut listed = HashSet::new();
+    for row in rows {
+        listed.insert(row.id.to_lowercase());
+        let Some(idx) = library.find_by_id(&row.id) else {
+            continue;
+        };
+        let loans = library.books[idx].loans.len();
+        let out = row.copies_total.saturating_sub(row.copies_available);
+        if (out as usize) < loans {
+            problems.push(format!(
+                "line {}: {} has {loans} recorded loan(s) but only {out} copy(ies) out",
+                row.line, row.id
+            ));
+        }
+    }
+    for book in &library.books {
+        let borrowed = borrowed_count(book);
+        if borrowed > 0 && !listed.contains(&book.id.to_lowercase()) {
+            problems.push(format!(
+                "{} \"{}\" is missing from the file but has {borrowed} copy(ies) on loan",
+                book.id, book.title
+            ));
+        }
+    }
+    problems
+}
+
+/// Replaces the catalog with `rows`. Books whose id is still present keep
+/// their other fields (genre, loans, suspension, and so on). Nothing is
+/// changed if any row conflicts with the loans on record.
+fn apply_catalog_csv(
+    library: &mut Library,
+    rows: Vec<CsvBook>,
+) -> Result<CsvImportSummary, Vec<String>> {
+    let problems = catalog_csv_conflicts(library, &rows);
+    if !problems.is_empty() {
+        return Err(problems);
+    }
+    let mut summary = CsvImportSummary::default();
+    let mut books = Vec::with_capacity(rows.len());
+    for row in rows {
+        let existing = library.find_by_id(&row.id);
+        let mut book = existing.map_or_else(Book::default, |idx| library.books[idx].clone());
+        let unchanged = book.id == row.id
+            && book.title == row.title
+            && book.author == row.author
+            && book.copies_total == row.copies_total
+            && book.copies_available == row.copies_available;
+        if existing.is_none() {
+            summary.added += 1;
+        } else if !unchanged {
+            summary.changed += 1;
+        }
+        if !unchanged {
+            book.id = row.id;
+            book.title = row.title;
+            book.author = row.author;
+            book.copies_total = row.copies_total;
+            book.copies_available = row.copies_available;
+            touch(&mut book);
+        }
+        books.push(book);
+    }
+    let kept = books.len() - summary.added;
+    summary.removed = library.books.len() - kept;
+    library.books = books;
+    library.rebuild_index();
+    Ok(summary)
+}
+
+fn export_catalog_csv(library: &Library) {
+    let Some(path) = read_choice("\nWrite CSV to (or press Enter to cancel): ") else {
+        return;
+    };
+    if path.is_empty() {
+        println!("Export cancelled.");
+        return;
+    }
+    let path = PathBuf::from(path);
+    if is_data_file(&path) {
+        println!("Refusing to overwrite the live data file; choose another path.");
+        return;
+    }
+    if path.exists() && !confirm(&format!("{} exists. Overwrite? [y/N]: ", path.display())) {
+        println!("Export cancelled.");
+        return;
+    }
+
+    let result = fs::File::create(&path)
+        .map_err(anyhow::Error::from)
+        .and_then(|file| write_catalog_csv(library, file));
+    match result {
+        Ok(()) => println!("Wrote {} book(s) to {}.", library.books.len(), path.display()),
+        Err(err) => println!("Could not write {}: {err}", path.display()),
+    }
+}
+
+fn import_catalog_csv(library: &mut Library, options: &Options) {
+    let Some(path) = read_choice("\nCSV file to import (or press Enter to cancel): ") else {
+        return;
+    };
+    if path.is_empty() {
+        println!("Import cancelled.");
+        return;
+    }
+    let rows = match fs::File::open(&path) {
+        Ok(file) => read_catalog_csv(file),
+        Err(err) => {
+            println!("Could not read {path}: {err}");
+            return;
+        }
+    };
+    let rows = rows.and_then(|rows| {
+        let problems = catalog_csv_conflicts(library, &rows);
+        if problems.is_empty() {
+            Ok(rows)
+        } else {
+            Err(problems)
+        }
+    });
+    let rows = match rows {
+        Ok(rows) => rows,
+        Err(problems) => {
+            println!("Rejected {path}; fix these and try again:");
+            for problem in problems {
+                println!("  {problem}");
+            }
+            println!("No changes made.");
+            return;
+        }
+    };
+
+    let keep: Vec<String> = rows.iter().map(|row| row.id.to_lowercase()).collect();
+    let dropped = indices_where(library, |book| !keep.contains(&book.id.to_lowercase()));
+    if !dropped.is_empty() {
+        println!("\nThese books are not in the file and will be removed:");
+        print_books(library, &dropped, Columns::COUNTS, options);
+    }
+    let prompt = format!("Replace the catalog with the {} book(s) in {path}? [y/N]: ", rows.len());
+    if !confirm(&prompt) {
+        println!("No changes made.");
+        return;
+    }
+
+    let Ok(summary) = apply_catalog_csv(library, rows) else {
+        // Conflicts were checked above, and nothing has changed since.
+        println!("No changes made.");
+        return;
+    };
+    if let Err(err) = save_data(library) {
+        eprintln!("Warning: could not save data: {err}");
+    }
+    println!(
+        "Imported {path}: {} changed, {} added, {} removed.",
+        summary.changed, summary.added, summary.removed
+    );
+}
+
+fn maintenance_menu(library: &mut Library, options: &Options, usage: &mut UsageStats) {
+    print_menu("Catalog Maintenance", MAINTENANCE_MENU);
+    let Some(choice) = read_choice("Choose an option: ") else {
+        return;
+    };
+    usage.record("Catalog Maintenance", MAINTENANCE_MENU, &choice);
+    match choice.as_str() {
+        "1" => rename_author(library, options),
+        "2" => merge_authors(library, options),
+        "3" => toggle_suspension(library, options),
+        "4" => print_spine_label(library, options),
+        "5" => check_copy_counts(library),
+        "6" => compact_save(library),
+        "7" => clear_review_flag(library, options),
+        "8" => import_missing_books(library),
+        "9" => replace_in_titles(library),
+        "10" => set_replacement_cost(library, options),
+        "11" => preview_copy_reduction(library, options),
+        "12" => export_clean_catalog(library),
+        "13" => batch_set_genre(library, options),
+        "14" => clean_up_zero_copy_books(library, options),
+        "15" => print_barcode_payload(library, options),
+        "16" => add_book(library),
+        "17" => remove_book(library, options),
+        "18" => export_catalog_csv(library),
+        "19" => import_catalog_csv(library, options),
+        "20" => fix_duplicate_ids(library),
+        _ => {}
+    }
+}
+
+fn menu() -> Option<String> {
+    print_menu("Library Menu", MAIN_MENU);
+    println!("c) Toggle compact listings for this session");
+    println!("s) Sort listings for this session");
+    println!("u) Undo the last borrow or return");
+    read_choice("Choose an option: ")
+}
+
+fn book_fields(book: &Book) -> serde_json::Map<String, serde_json::Value> {
+    match serde_json::to_value(book) {
+        Ok(serde_json::Value::Object(fields)) => fields,
+        _ => serde_json::Map::new(),
+    }
+}
+
+/// Field-level differences between two versions of the same book, as
+/// `(field, old, new)` with values rendered as JSON. Fields left at their
+/// serde default are not serialized, so they show up as "(default)".
+fn field_changes(old: &Book, new: &Book) -> Vec<(String, String, String)> {
+    let old_fields = book_fields(old);
+    let new_fields = book_fields(new);
+    let mut names: Vec<&String> = old_fields.keys().collect();
+    names.extend(new_fields.keys().filter(|name| !old_fields.contains_key(*name)));
+
+    let render = |value: Option<&serde_json::Value>| {
+        value.map_or_else(|| "(default)".to_string(), |v| v.to_string())
+    };
+    names
+        .into_iter()
+        .filter_map(|name| {
+            let before = old_fields.get(name);
+            let after = new_fields.get(name);
+            (before != after).then(|| (name.clone(), render(before), render(after)))
+        })
+        .collect()
+}
+
+fn print_library_diff(current: &Library, other: &Library) -> usize {
+    let mut differences = 0;
+    for book in &current.books {
+        match other.find_by_id(&book.id) {
+            None => {
+                println!("- {} \"{}\" by {}", book.id, book.title, book.author);
+                differences += 1;
+            }
+            Some(idx) => {
+                let changes = field_changes(book, &other.books[idx]);
+                if !changes.is_empty() {
+                    println!("~ {} \"{}\"", book.id, book.title);
+                    for (field, before, after) in changes {
+                        println!("    {field}: {before} -> {after}");
+                    }
+                    differences += 1;
+                }
+            }
+        }
+    }
+    for book in &other.books {
+        if current.find_by_id(&book.id).is_none() {
+            println!("+ {} \"{}\" by {}", book.id, book.title, book.author);
+            differences += 1;
+        }
+    }
+    differences
+}
+
+fn run_diff(other_path: &Path) -> Result<()> {
+    let current = read_library_file(&data_path())
+        .map_err(|err| err.context(format!("could not read {}", data_path().display())))?;
+    let other = read_library_file(other_path)
+        .map_err(|err| err.context(format!("could not read {}", other_path.display())))?;
+
+    println!("Comparing {} -> {}", data_path().display(), other_path.display());
+    if print_library_diff(&current, &other) == 0 {
+        println!("No differences.");
+    }
+    Ok(())
+}
+
+fn run_command(library: &mut Library, command: Command, options: &Options) -> Result<()> {
+    let mut history = None;
+    let done = match command {
+        Command::List { sort, descending } => {
+            let mut indices: Vec<usize> = (0..library.books.len()).collect();
+            if let Some(key) = sort {
+                sort_indices(library, &mut indices, SortOrder { key, descending });
+            }
+            print_books(library, &indices, Columns::ALL, options);
+            return Ok(());
+        }
+        Command::Borrow { id, borrower, days } => {
+            let loan = match borrower {
+                Some(borrower) => {
+                    let due = due_date(days).context("loan length is too long")?;
+                    Some(Loan { borrower, due })
+                }
+                None => None,
+            };
+            let due = loan.as_ref().map(|loan| format!(" It is due back on {}.", loan.due));
+            let borrower = loan.as_ref().map(|loan| loan.borrower.clone());
+            let book = library.borrow_by_id(&id, loan)?;
+            history = Some(history_entry(book, HistoryAction::Borrow, borrower.as_deref()));
+            format!("Borrowed \"{}\".{}", book.title, due.unwrap_or_default())
+        }
+        Command::Return { id, borrower } => {
+            let (book, loan) =
+                library.return_by_id(&id, borrower.as_deref(), ReturnCondition::Good)?;
+            let borrower = loan.map(|loan| loan.borrower);
+            history = Some(history_entry(book, HistoryAction::Return, borrower.as_deref()));
+            format!("Returned \"{}\".", book.title)
+        }
+        Command::ExportCsv { path } => {
+            if path.as_os_str() == "-" {
+                write_catalog_csv(library, io::stdout())?;
+            } else {
+                if is_data_file(&path) {
+                    bail!("refusing to overwrite the live data file {}", path.display());
+                }
+                let file = fs::File::create(&path)
+                    .with_context(|| format!("could not create {}", path.display()))?;
+                write_catalog_csv(library, file)?;
+            }
+            return Ok(());
+        }
+        Command::ImportCsv { path } => {
+            let file = fs::File::open(&path)
+                .with_context(|| format!("could not read {}", path.display()))?;
+            let rejected = |problems: Vec<String>| {
+                anyhow!("rejected {}:\n  {}", path.display(), problems.join("\n  "))
+            };
+            let rows = read_catalog_csv(file).map_err(rejected)?;
+            let summary = apply_catalog_csv(library, rows).map_err(rejected)?;
+            format!(
+                "Imported {}: {} changed, {} added, {} removed.",
+                path.display(),
+                summary.changed,
+                summary.added,
+                summary.removed
+            )
+        }
+        Command::Add { title, author, copies, cost } => {
+            let book = library.add_book(title, author, copies, cost.unwrap_or_default())?;
+            format!("Added \"{}\" as {}.", book.title, book.id)
+        }
+    };
+    save_upgrade(library);
+    save_data(library).context("could not save data")?;
+    println!("{done}");
+    if let Some(entry) = &history {
+        record_history(entry);
+    }
+    Ok(())
+}
+
+fn main() {
+    let mut options = Options::parse();
+    let _ = DATA_PATH.set(resolve_data_path(options.data.as_deref()));
+    if let Some(other_path) = &options.diff {
+        if let Err(err) = run_diff(other_path) {
+            eprintln!("Error: {err:#}");
+            std::process::exit(1);
+        }
+        return;
+    }
+    let mut library = load_data();
+    if options.kpi {
+        println!("{}", kpi_line(&library));
+        return;
+    }
+    if let Some(command) = options.command.take() {
+        if let Err(err) = run_command(&mut library, command, &options) {
+            eprintln!("Error: {err:#}");
+            std::process::exit(1);
+        }
+        return;
+    }
+    save_upgrade(&mut library);
+    if let Some(count) = options.seed_demo {
+        if let Err(err) = seed_demo(&mut library, count) {
+            eprintln!("Error: {err:#}");
+            std::process::exit(1);
+        }
+        match save_data(&library) {
+            Ok(()) => println!(
+                "Added {count} demo book(s); catalog now has {}.",
+                library.books.len()
+            ),
+            Err(err) => eprintln!("Warning: could not save data: {err}"),
+        }
+        return;
+    }
+
+    let mut usage = load_usage_stats();
+    let mut undo = Vec::new();
+    loop {
+        let choice = menu();
+        // Start every action from the latest shared copy; save_data refuses
+        // to overwrite changes made while the action was in progress.
+        reload_shared(&mut library);
+        if let Some(choice) = &choice {
+            usage.record("", MAIN_MENU, choice);
+            if choice.eq_ignore_ascii_case("c") {
+                usage.record_action("Toggle compact listings");
+            } else if choice.eq_ignore_ascii_case("s") {
+                usage.record_action("Sort listings");
+            } else if choice.eq_ignore_ascii_case("u") {
+                usage.record_action("Undo last borrow or return");
+            }
+        }
+        match choice.as_deref() {
+            Some("1") => view_available(&library, &options),
+            Some("2") => view_borrowed(&library, &options),
+            Some("3") => borrow_book(&mut library, &options, &mut undo),
+            Some("4") => return_book(&mut library, &options, &mut undo),
+            Some("5") => search_all_fields(&library, &options),
+            Some("6") => view_all(&library, &options),
+            Some("7") => filter_by_status(&library, &options),
+            Some("8") => quick_filter(&library, &options),
+            Some("9") => search_and_borrow(&mut library, &options, &mut undo),
+            Some("10") => maintenance_menu(&mut library, &options, &mut usage),
+            Some("11") => reports_menu(&library, &options, &mut usage),
+            Some("12") => settings_menu(&mut library, &mut options, &mut usage),
+            Some("13") => {
+                println!("Goodbye!");
+                break;
+            }
+            Some("c") | Some("C") => toggle_session_compact(&library, &mut options),
+            Some("s") | Some("S") => choose_sort_order(&mut options),
+            Some("u") | Some("U") => undo_last(&mut library, &mut undo),
+            Some(_) => println!("Please choose a valid option (1-13, c, s, or u)."),
+            None => {
+                println!("Input error. Exiting.");
+                break;
+            }
+        }
+
+        if library.settings.persist_usage_stats {
+            if let Err(err) = save_usage_stats(&usage) {
+                eprintln!("Warning: could not save usage statistics: {err}");
+            }
+        }
+        let _ = read_choice("\nPress Enter to continue...");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn library_with(books: Vec<Book>) -> Library {
+        let mut library = Library {
+            version: DATA_VERSION,
+            books,
+            settings: Settings::default(),
+            id_index: HashMap::new(),
+            file_mtime: Cell::new(None),
+            upgraded_from: None,
+        };
+        library.rebuild_index();
+        library
+    }
+
+    fn book(id: &str, total: u32, available: u32) -> Book {
+        Book {
+            id: id.into(),
+            title: format!("Title {id}"),
+            author: "Author".into(),
+            copies_total: total,
+            copies_available: available,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn borrowing_the_last_copy_succeeds_once() {
+        let mut library = library_with(vec![book("B001", 2, 1)]);
+        let borrowed = library.borrow_by_id("b001", None).unwrap();
+        assert_eq!(borrowed.copies_available, 0);
+        assert_eq!(
+            library.borrow_by_id("B001", None).unwrap_err(),
+            LendError::NoCopiesLeft("Title B001".into())
+        );
+        assert_eq!(library.books[0].copies_available, 0);
+    }
+
+    #[test]
+    fn borrowing_an_unknown_id_fails() {
+        let mut library = library_with(vec![book("B001", 1, 1)]);
+        assert_eq!(
+            library.borrow_by_id("B999", None).unwrap_err(),
+            LendError::UnknownId("B999".into())
+        );
+        assert_eq!(library.books[0].copies_available, 1);
+    }
+
+    #[test]
+    fn borrowing_respects_suspension_and_the_checkout_limit() {
+        let mut suspended = book("B001", 1, 1);
+        suspended.suspended = true;
+        let mut library = library_with(vec![suspended, book("B002", 3, 2)]);
+        assert!(matches!(library.borrow_by_id("B001", None), Err(LendError::Suspended(_))));
+
+        library.settings.checkout_limit = Some(1);
+        assert_eq!(library.borrow_by_id("B002", None).unwrap_err(), LendError::LimitReached(1));
+    }
+
+    #[test]
+    fn returning_when_all_copies_are_in_fails() {
+        let mut library = library_with(vec![book("B001", 2, 2)]);
+        assert_eq!(
+            library.return_by_id("B001", None, ReturnCondition::Good).unwrap_err(),
+            LendError::AllCopiesIn("Title B001".into())
+        );
+    }
+
+    #[test]
+    fn returning_ends_the_named_loan() {
+        let due = NaiveDate::from_ymd_opt(2026, 1, 1).unwrap();
+        let mut library = library_with(vec![book("B001", 2, 2)]);
+        for borrower in ["Ann", "Bob"] {
+            let loan = Loan { borrower: borrower.into(), due };
+            library.borrow_by_id("B001", Some(loan)).unwrap();
+        }
+        assert!(matches!(
+            library.return_by_id("B001", None, ReturnCondition::Good),
+            Err(LendError::BorrowerRequired(_))
+        ));
+        assert!(matches!(
+            library.return_by_id("B001", Some("Cy"), ReturnCondition::Good),
+            Err(LendError::UnknownBorrower { .. })
+        ));
+
+        let (returned, loan) =
+            library.return_by_id("B001", Some("ann"), ReturnCondition::Good).unwrap();
+        assert_eq!(loan.map(|loan| loan.borrower), Some("Ann".to_string()));
+        assert_eq!(returned.copies_available, 1);
+        assert_eq!(returned.loans.len(), 1);
+        assert_eq!(returned.loans[0].borrower, "Bob");
+    }
+
+    #[test]
+    fn reverting_restores_counts_and_loans() {
+        let due = NaiveDate::from_ymd_opt(2026, 1, 1).unwrap();
+        let loan = Loan { borrower: "Ann".into(), due };
+        let mut library = library_with(vec![book("B001", 2, 2)]);
+        library.borrow_by_id("B001", Some(loan.clone())).unwrap();
+        let borrow = UndoEntry::Borrow { book_id: "B001".into(), loan: Some(loan.clone()) };
+        assert_eq!(revert(&mut library, &borrow), Ok(0));
+        assert_eq!(library.books[0].copies_available, 2);
+        assert!(library.books[0].loans.is_empty());
+        assert!(revert(&mut library, &borrow).is_err());
+
+        library.borrow_by_id("B001", Some(loan.clone())).unwrap();
+        take_back(&mut library.books[0], Some(0), ReturnCondition::Lost);
+        let lost = UndoEntry::Return {
+            book_id: "B001".into(),
+            loan: Some(loan.clone()),
+            condition: ReturnCondition::Lost,
+            was_flagged: false,
+        };
+        revert(&mut library, &lost).unwrap();
+        assert_eq!(library.books[0].copies_total, 2);
+        assert_eq!(library.books[0].copies_available, 1);
+        assert_eq!(library.books[0].loans, vec![loan.clone()]);
+
+        // Someone else returned Ann's copy and Bob borrowed it since.
+        library.return_by_id("B001", Some("Ann"), ReturnCondition::Good).unwrap();
+        let bob = Loan { borrower: "Bob".into(), due };
+        library.borrow_by_id("B001", Some(bob)).unwrap();
+        let borrow = UndoEntry::Borrow { book_id: "B001".into(), loan: Some(loan) };
+        let err = revert(&mut library, &borrow).unwrap_err();
+        assert!(err.contains("no longer on record"), "{err}");
+        assert_eq!(library.books[0].copies_available, 1);
+        assert_eq!(library.books[0].loans.len(), 1);
+    }
+
+    #[test]
+    fn version_one_files_are_upgraded() {
+        let text = r#"{"books": [{"id": "B001", "title": "1984", "author": "George Orwell",
+            "copies_total": 3, "copies_available": 2}]}"#;
+        let (library, found) = parse_versioned_library(text).unwrap();
+        assert_eq!(found, 1);
+        assert_eq!(library.version, DATA_VERSION);
+        assert!(library.books[0].loans.is_empty());
+        assert_eq!(unrecorded_loans(&library.books[0]), 1);
+
+        let saved = serde_json::to_string(&library).unwrap();
+        assert_eq!(parse_versioned_library(&saved).unwrap().1, DATA_VERSION);
+    }
+
+    #[test]
+    fn newer_files_are_refused() {
+        let text = format!(r#"{{"version": {}, "books": []}}"#, DATA_VERSION + 1);
+        let Err(err) = parse_library(&text) else {
+            panic!("a newer version should not load");
+        };
+        assert!(err.is::<FormatError>(), "{err}");
+        let unreadable = [r#"{"version": "two", "books": []}"#, r#"{"version": 1.5, "books": []}"#];
+        for text in unreadable.into_iter().chain(["[]"]) {
+            let err = parse_library(text).err().expect("should not load");
+            assert!(err.is::<FormatError>(), "{text}: {err}");
+        }
+    }
+
+    #[test]
+    fn importing_skips_present_books_and_rejects_bad_counts() {
+        let mut library = library_with(vec![book("B001", 1, 1)]);
+        let mut lent = book("B003", 1, 1);
+        lent.loans.push(Loan {
+            borrower: "Ann".into(),
+            due: NaiveDate::from_ymd_opt(2026, 1, 1).unwrap(),
+        });
+        let source =
+            library_with(vec![book("b001", 2, 2), book("B002", 1, 3), lent, book("B004", 2, 1)]);
+
+        let (added, skipped, rejected) = import_missing(&mut library, source);
+        assert_eq!((added, skipped), (1, 1));
+        assert_eq!(rejected.len(), 2, "{rejected:?}");
+        assert!(library.find_by_id("B004").is_some());
+        assert!(library.find_by_id("B002").is_none());
+    }
+
+    #[test]
+    fn demo_seeding_is_repeatable_and_bounded() {
+        let seeded = || {
+            let mut library = library_with(vec![book("B001", 1, 1)]);
+            seed_demo(&mut library, 5).unwrap();
+            serde_json::to_string(&library).unwrap()
+        };
+        assert_eq!(seeded(), seeded());
+
+        let mut library = library_with(vec![book(&format_book_id(u32::MAX - 1), 1, 1)]);
+        assert!(seed_demo(&mut library, 2).is_err());
+        assert!(seed_demo(&mut library, usize::MAX).is_err());
+        assert_eq!(library.books.len(), 1);
+    }
+
+    #[test]
+    fn case_insensitive_replace_handles_length_changing_letters() {
+        let replace = |text, find, with| replace_case_insensitive(text, find, with);
+        assert_eq!(replace("The Cat and the cat", "CAT", "Dog"), "The Dog and the Dog");
+        assert_eq!(replace("İstanbul Nights", "nights", "Days"), "İstanbul Days");
+        assert_eq!(replace("Guide to İzmir", "İZMIR", "Ankara"), "Guide to Ankara");
+        assert_eq!(replace("Dune", "x", "y"), "Dune");
+    }
+
+    #[test]
+    fn embedded_defaults_parse() {
+        let library = parse_library(EMBEDDED_DEFAULTS).unwrap();
+        assert!(!library.books.is_empty());
+        assert!(validation_warnings(&library).is_empty());
+    }
+
+    #[test]
+    fn adding_uses_the_next_free_id() {
+        let mut library = library_with(vec![book("B001", 1, 1), book("B007", 1, 1)]);
+        let added = library.add_book("New".into(), "Someone".into(), 3, 0.0).unwrap();
+        assert_eq!(added.id, "B008");
+        assert_eq!(added.copies_available, 3);
+        assert_eq!(library.find_by_id("b008"), Some(2));
+        assert_eq!(
+            library.add_book("Empty".into(), "Someone".into(), 0, 0.0).unwrap_err(),
+            LendError::NoCopies
+        );
+        assert_eq!(
+            library.add_book(" ".into(), "Someone".into(), 1, 0.0).unwrap_err(),
+            LendError::MissingField("title")
+        );
+        assert_eq!(library.books.len(), 3);
+
+        let mut library = library_with(vec![book("b009", 1, 1)]);
+        assert_eq!(library.add_book("New".into(), "Someone".into(), 1, 0.0).unwrap().id, "B010");
+        let mut library = library_with(vec![book(&format_book_id(u32::MAX), 1, 1)]);
+        assert_eq!(
+            library.add_book("New".into(), "Someone".into(), 1, 0.0).unwrap_err(),
+            LendError::IdsExhausted
+        );
+    }
+
+    #[test]
+    fn sorting_ignores_case_and_breaks_ties_by_id() {
+        let mut books = vec![book("B003", 2, 1), book("B001", 2, 2), book("B002", 2, 1)];
+        books[0].title = "apple".into();
+        books[1].title = "Banana".into();
+        books[2].title = "Apple".into();
+        let library = library_with(books);
+        let order = |key, descending| {
+            let mut indices = vec![0, 1, 2];
+            sort_indices(&library, &mut indices, SortOrder { key, descending });
+            indices
+        };
+
+        assert_eq!(order(SortKey::Title, false), vec![2, 0, 1]);
+        assert_eq!(order(SortKey::Title, true), vec![1, 2, 0]);
+        assert_eq!(order(SortKey::Available, false), vec![2, 0, 1]);
+        assert_eq!(order(SortKey::Available, true), vec![1, 2, 0]);
+        assert_eq!(order(SortKey::Id, true), vec![0, 2, 1]);
+    }
+
+    #[test]
+    fn csv_round_trip_keeps_quoted_titles() {
+        let mut original = library_with(vec![book("B001", 3, 1), book("B002", 2, 2)]);
+        original.books[0].title = "Pride, and \"Prejudice\"".into();
+        original.books[0].genre = "Classic".into();
+        let mut text = Vec::new();
+        write_catalog_csv(&original, &mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.starts_with("id,title,author,copies_total,copies_available\n"));
+
+        let rows = read_catalog_csv(text.as_bytes()).unwrap();
+        assert_eq!(rows[0].title, "Pride, and \"Prejudice\"");
+        let summary = apply_catalog_csv(&mut original, rows).unwrap();
+        assert_eq!(summary, CsvImportSummary::default());
+        assert_eq!(original.books[0].genre, "Classic");
+    }
+
+    #[test]
+    fn csv_import_reports_every_problem() {
+        let text = "id,title,author,copies_total,copies_available\n\
+                    B001,One,A,2,3\n\
+                    B002,Two,B,1,1\n\
+                    b002,Three,C,1,x\n\
+                    b002,Four,D,1,1\n";
+        let problems = read_catalog_csv(text.as_bytes()).unwrap_err();
+        assert_eq!(problems.len(), 3, "{problems:?}");
+        assert!(problems[0].starts_with("line 2: B001 has 3 available"));
+        assert!(problems[1].starts_with("line 4:"));
+        assert_eq!(problems[2], "line 5: id b002 is already used on line 3");
+    }
+
+    #[test]
+    fn csv_import_adds_changes_and_removes_books() {
+        let mut library = library_with(vec![book("B001", 1, 1), book("B002", 1, 1)]);
+        let text = "id,title,author,copies_total,copies_available\n\
+                    B001,Renamed,Author,1,1\n\
+                    B003,New,Someone,2,2\n";
+        let rows = read_catalog_csv(text.as_bytes()).unwrap();
+        let summary = apply_catalog_csv(&mut library, rows).unwrap();
+        assert_eq!(summary, CsvImportSummary { changed: 1, added: 1, removed: 1 });
+        assert_eq!(library.books[0].title, "Renamed");
+        assert_eq!(library.find_by_id("B003"), Some(1));
+        assert_eq!(library.find_by_id("B002"), None);
+    }
+
+    #[test]
+    fn csv_import_rejects_empty_and_foreign_files() {
+        let empty = read_catalog_csv("".as_bytes()).unwrap_err();
+        assert!(empty[0].starts_with("line 1: expected the header"), "{empty:?}");
+
+        let header = "id,title,author,copies_total,copies_available\n";
+        let header_only = read_catalog_csv(header.as_bytes()).unwrap_err();
+        assert_eq!(header_only, vec!["the file lists no books".to_string()]);
+
+        let foreign = read_catalog_csv("title,id\nDune,B001\n".as_bytes()).unwrap_err();
+        assert_eq!(foreign.len(), 1, "{foreign:?}");
+    }
+
+    #[test]
+    fn csv_import_rejects_rows_that_contradict_loans() {
+        let due = NaiveDate::from_ymd_opt(2026, 1, 1).unwrap();
+        let mut library = library_with(vec![book("B001", 2, 2), book("B002", 1, 1)]);
+        for id in ["B001", "B002"] {
+            let loan = Loan { borrower: "Ann".into(), due };
+            library.borrow_by_id(id, Some(loan)).unwrap();
+        }
+        let text = "id,title,author,copies_total,copies_available\n\
+                    B001,Title B001,Author,2,2\n";
+        let rows = read_catalog_csv(text.as_bytes()).unwrap();
+        let problems = apply_catalog_csv(&mut library, rows).unwrap_err();
+        assert_eq!(problems.len(), 2, "{problems:?}");
+        assert!(problems[0].starts_with("line 2: B001 has 1 recorded loan(s)"));
+        assert!(problems[1].starts_with("B002 \"Title B002\" is missing"));
+        assert_eq!(library.books.len(), 2);
+        assert_eq!(library.books[0].copies_available, 1);
+    }
+
+    #[test]
+    fn validation_reports_duplicate_ids_and_overcounts() {
+        let library = library_with(vec![
+            book("B001", 1, 1),
+            book("B002", 2, 3),
+            book("b001", 1, 1),
+            book("B001", 1, 1),
+        ]);
+        assert_eq!(duplicate_ids(&library), vec![("B001".to_string(), vec![0, 2, 3])]);
+
+        let warnings = validation_warnings(&library);
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].starts_with("id B001 is used by 3 books"));
+        assert!(warnings[1].starts_with("B002 has more copies available than in total"));
+        assert!(validation_warnings(&library_with(vec![book("B001", 1, 0)])).is_empty());
+    }
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("book-lending-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn interrupted_write_leaves_original_intact() {
+        let dir = scratch_dir("atomic");
+        let path = dir.join(DATA_FILE);
+        let original = library_with(vec![book("A1", 2, 2)]);
+        let text = serde_json::to_string_pretty(&original).unwrap();
+        write_atomically(&path, &text).unwrap();
+
+        // A crash mid-write leaves a truncated temp file and no rename.
+        fs::write(temp_path(&path), &text[..text.len() / 2]).unwrap();
+        let loaded = read_library_file(&path).unwrap();
+        assert_eq!(loaded.books.len(), 1);
+        assert_eq!(loaded.books[0].id, "A1");
+
+        let updated = library_with(vec![book("A1", 2, 2), book("B2", 1, 1)]);
+        write_atomically(&path, &serde_json::to_string_pretty(&updated).unwrap()).unwrap();
+        assert_eq!(read_library_file(&path).unwrap().books.len(), 2);
+        assert!(!temp_path(&path).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn backups_keep_previous_contents_and_are_pruned() {
+        let dir = scratch_dir("backup");
+        let path = dir.join(DATA_FILE);
+        fs::write(&path, "first").unwrap();
+
+        let backup = backup_file(&path).unwrap();
+        assert_eq!(fs::read_to_string(&backup).unwrap(), "first");
+
+        for _ in 0..BACKUP_LIMIT + 2 {
+            backup_file(&path).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(2));
+        }
+        let kept = fs::read_dir(dir.join(BACKUP_DIR)).unwrap().count();
+        assert_eq!(kept, BACKUP_LIMIT);
+
+        let damaged = preserve_file(&path, "corrupt").unwrap();
+        for _ in 0..BACKUP_LIMIT + 2 {
+            backup_file(&path).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(2));
+        }
+        assert_eq!(fs::read_to_string(&damaged).unwrap(), "first");
+        let kept = fs::read_dir(dir.join(BACKUP_DIR)).unwrap().count();
+        assert_eq!(kept, BACKUP_LIMIT + 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn expand_home_only_rewrites_a_leading_tilde() {
+        let home = Path::new("/home/ann");
+        assert_eq!(
+            expand_home(Path::new("~/.local/share/library.json"), Some(home)),
+            PathBuf::from("/home/ann/.local/share/library.json")
+        );
+        assert_eq!(expand_home(Path::new("~"), Some(home)), PathBuf::from("/home/ann"));
+        assert_eq!(
+            expand_home(Path::new("data/~x.json"), Some(home)),
+            PathBuf::from("data/~x.json")
+        );
+        assert_eq!(expand_home(Path::new("~/x.json"), None), PathBuf::from("~/x.json"));
+    }
+}